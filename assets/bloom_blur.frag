@@ -0,0 +1,22 @@
+#version 410 core
+in vec2 TexCoord;
+
+out vec4 FragColor;
+
+uniform sampler2D image;
+uniform bool horizontal;
+
+const float weights[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+void main()
+{
+    vec2 texelSize = 1.0 / vec2(textureSize(image, 0));
+    vec2 step = horizontal ? vec2(texelSize.x, 0.0) : vec2(0.0, texelSize.y);
+
+    vec3 result = texture(image, TexCoord).rgb * weights[0];
+    for (int i = 1; i < 5; ++i) {
+        result += texture(image, TexCoord + step * float(i)).rgb * weights[i];
+        result += texture(image, TexCoord - step * float(i)).rgb * weights[i];
+    }
+    FragColor = vec4(result, 1.0);
+}
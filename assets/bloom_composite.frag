@@ -0,0 +1,14 @@
+#version 410 core
+in vec2 TexCoord;
+
+out vec4 FragColor;
+
+uniform sampler2D scene;
+uniform sampler2D bloom;
+uniform float intensity;
+
+void main()
+{
+    vec3 color = texture(scene, TexCoord).rgb + texture(bloom, TexCoord).rgb * intensity;
+    FragColor = vec4(color, 1.0);
+}
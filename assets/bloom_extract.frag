@@ -0,0 +1,16 @@
+#version 410 core
+in vec2 TexCoord;
+
+out vec4 FragColor;
+
+uniform sampler2D scene;
+uniform float threshold;
+
+void main()
+{
+    vec3 color = texture(scene, TexCoord).rgb;
+    float brightness = max(color.r, max(color.g, color.b));
+    // Keep only the part of the color above the threshold.
+    float contribution = max(brightness - threshold, 0.0) / max(brightness, 0.0001);
+    FragColor = vec4(color * contribution, 1.0);
+}
@@ -0,0 +1,7 @@
+// Written once per frame by the Camera System, see `CameraBlock` in components.rs.
+layout(std140) uniform Camera {
+    mat4 view;
+    mat4 projection;
+    mat4 inverseViewProjection;
+    vec3 viewPos;
+};
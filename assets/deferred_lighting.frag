@@ -0,0 +1,58 @@
+#version 410 core
+in vec2 TexCoord;
+
+out vec4 FragColor;
+
+// Lighting pass of the deferred path, reads the G-buffer written by gbuffer.frag.
+uniform sampler2D gAlbedo;
+uniform sampler2D gNormal;
+uniform sampler2D gMaterial;
+uniform sampler2D gEmissive;
+uniform sampler2D gDepth;
+
+#include "lighting.glsl"
+#include "brdf.glsl"
+
+void main()
+{
+    float depth = texture(gDepth, TexCoord).r;
+    if (depth >= 1.0) {
+        // Nothing was drawn here, leave it to the skybox or the clear color.
+        discard;
+    }
+    vec4 clip = vec4(TexCoord * 2.0 - 1.0, depth * 2.0 - 1.0, 1.0);
+    vec4 world = inverseViewProjection * clip;
+    vec3 FragPos = world.xyz / world.w;
+
+    vec4 albedo = texture(gAlbedo, TexCoord);
+    vec3 baseColor = albedo.rgb;
+    float occlusion = albedo.a;
+    vec3 N = normalize(texture(gNormal, TexCoord).xyz);
+    vec2 metallicRoughness = texture(gMaterial, TexCoord).rg;
+    float metallic = metallicRoughness.r;
+    float roughness = metallicRoughness.g;
+
+    vec3 V = normalize(viewPos - FragPos);
+    float NdotV = max(dot(N, V), 1e-4);
+    vec3 F0 = mix(vec3(0.04), baseColor, metallic);
+
+    vec3 Lo = vec3(0.0);
+    for (int i = 0; i < min(lightCount, MAX_LIGHTS); ++i) {
+        vec3 L;
+        vec3 radiance = lightRadiance(lights[i], FragPos, L) * shadowFactor(lights[i], FragPos, N, L);
+        vec3 H = normalize(V + L);
+        float NdotL = max(dot(N, L), 0.0);
+        float NdotH = max(dot(N, H), 0.0);
+
+        vec3 F = fresnelSchlick(max(dot(H, V), 0.0), F0);
+        vec3 specular = distributionGGX(NdotH, roughness) * geometrySmith(NdotV, NdotL, roughness) * F / (4.0 * NdotV * max(NdotL, 1e-4));
+        vec3 kD = (1.0 - F) * (1.0 - metallic);
+        Lo += (kD * baseColor / PI + specular) * radiance * NdotL;
+    }
+
+    vec3 ambient = ambientLighting(N, V, NdotV, F0, baseColor, metallic, roughness) * occlusion;
+    vec3 color = ambient + Lo + texture(gEmissive, TexCoord).rgb;
+    FragColor = vec4(color, 1.0);
+    // Later forward passes depth test against the G-buffer depth.
+    gl_FragDepth = depth;
+}
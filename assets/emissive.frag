@@ -0,0 +1,26 @@
+#version 410 core
+in vec3 FragPos;
+in vec3 Normal;
+in vec2 TexCoord;
+
+out vec4 FragColor;
+
+struct Emission {
+    vec3 color;
+    float intensity;
+};
+
+uniform Emission emission;
+
+#include "camera.glsl"
+
+void main()
+{
+    // Brightest where the orb faces the viewer, fading towards the silhouette.
+    vec3 viewDir = normalize(viewPos - FragPos);
+    float facing = max(dot(normalize(Normal), viewDir), 0.0);
+    float glow = mix(0.4, 1.0, facing);
+
+    // Left unclamped on purpose, values above 1 are what the bloom pass picks up.
+    FragColor = vec4(emission.color * emission.intensity * glow, 1.0);
+}
@@ -0,0 +1,55 @@
+#version 410 core
+in vec3 FragPos;
+in vec3 Normal;
+in vec2 TexCoord;
+
+// Geometry pass of the deferred path, see deferred.rs.
+layout (location = 0) out vec4 gAlbedo;   // rgb base color, a occlusion
+layout (location = 1) out vec4 gNormal;   // xyz world normal
+layout (location = 2) out vec4 gMaterial; // r metallic, g roughness
+layout (location = 3) out vec4 gEmissive; // rgb emissive
+
+#include "material.glsl"
+
+void main()
+{
+    vec4 baseColor = material.baseColor;
+    if (material.hasBaseColorTexture) {
+        vec4 texel = texture(material.baseColorTexture, TexCoord);
+        baseColor *= vec4(pow(texel.rgb, vec3(2.2)), texel.a);
+    }
+    if (material.alphaMode == ALPHA_MASK && baseColor.a < material.alphaCutoff) {
+        discard;
+    }
+
+    float metallic = material.metallic;
+    float roughness = material.roughness;
+    if (material.hasMetallicRoughnessTexture) {
+        // glTF packs roughness in G and metalness in B.
+        vec4 texel = texture(material.metallicRoughnessTexture, TexCoord);
+        roughness *= texel.g;
+        metallic *= texel.b;
+    }
+
+    vec3 N = normalize(Normal);
+    if (material.hasNormalTexture) {
+        vec3 tangentNormal = texture(material.normalTexture, TexCoord).xyz * 2.0 - 1.0;
+        tangentNormal.xy *= material.normalScale;
+        N = perturbNormal(N, normalize(tangentNormal));
+    }
+
+    float occlusion = 1.0;
+    if (material.hasOcclusionTexture) {
+        occlusion = mix(1.0, texture(material.occlusionTexture, TexCoord).r, material.occlusionStrength);
+    }
+
+    vec3 emissive = material.emissive;
+    if (material.hasEmissiveTexture) {
+        emissive *= pow(texture(material.emissiveTexture, TexCoord).rgb, vec3(2.2));
+    }
+
+    gAlbedo = vec4(baseColor.rgb, occlusion);
+    gNormal = vec4(N, 0.0);
+    gMaterial = vec4(metallic, clamp(roughness, 0.04, 1.0), 0.0, 0.0);
+    gEmissive = vec4(emissive, 1.0);
+}
@@ -0,0 +1,113 @@
+// Scene lights, MAX_LIGHTS, MAX_CASCADES and MAX_SPOT_SHADOWS are defined by `load_shader`.
+// Shadows are sampled when SHADOWS is defined, otherwise every light is unshadowed.
+#include "camera.glsl"
+
+// Filled once per frame by the Light System, see `LightBlock` in components.rs.
+const int LIGHT_DIRECTIONAL = 0;
+const int LIGHT_POINT = 1;
+const int LIGHT_SPOT = 2;
+
+struct Light {
+    vec4 position;  // xyz, w = kind
+    vec4 direction; // xyz, w = range
+    vec4 color;     // rgb premultiplied by intensity
+    vec4 cone;      // x = cos(inner angle), y = cos(outer angle)
+    vec4 shadow;    // x = shadow slot (-1 for none), y = depth bias, z = normal bias, w = pcf radius
+};
+
+layout(std140) uniform Lights {
+    Light lights[MAX_LIGHTS];
+    int lightCount;
+};
+
+// Direction towards the light and its attenuated radiance at `fragPos`.
+vec3 lightRadiance(Light light, vec3 fragPos, out vec3 L)
+{
+    int kind = int(light.position.w);
+    if (kind == LIGHT_DIRECTIONAL) {
+        L = normalize(-light.direction.xyz);
+        return light.color.rgb;
+    }
+
+    vec3 toLight = light.position.xyz - fragPos;
+    float distance = length(toLight);
+    L = toLight / max(distance, 1e-4);
+
+    // Inverse square falloff windowed to reach zero at the light's range.
+    float range = light.direction.w;
+    float window = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+    float attenuation = window * window / (distance * distance + 1.0);
+
+    if (kind == LIGHT_SPOT) {
+        float cosAngle = dot(-L, normalize(light.direction.xyz));
+        attenuation *= smoothstep(light.cone.y, light.cone.x, cosAngle);
+    }
+    return light.color.rgb * attenuation;
+}
+
+#ifdef SHADOWS
+// Written once per frame by `ShadowRenderer`, see `ShadowBlock` in shadows.rs.
+
+layout(std140) uniform Shadows {
+    mat4 cascadeMatrices[MAX_CASCADES];
+    vec4 cascadeSplits;  // far view depth of each cascade
+    mat4 spotMatrices[MAX_SPOT_SHADOWS];
+    vec4 cameraForward;  // xyz, w = cascade count
+};
+
+uniform sampler2DArrayShadow cascadeShadowMap;
+uniform sampler2DArrayShadow spotShadowMap;
+
+// PCF over a (2 * radius + 1)^2 kernel, on top of the hardware 2x2 comparison filter.
+float sampleShadow(sampler2DArrayShadow shadowMap, mat4 lightMatrix, int layer, vec3 fragPos, vec3 normal, vec3 L, vec4 shadow)
+{
+    float NdotL = max(dot(normal, L), 0.0);
+    vec3 offsetPos = fragPos + normal * shadow.z * (1.0 - NdotL);
+    vec4 clip = lightMatrix * vec4(offsetPos, 1.0);
+    vec3 coords = clip.xyz / clip.w * 0.5 + 0.5;
+    if (coords.z > 1.0) {
+        return 1.0;
+    }
+
+    float bias = max(shadow.y * (1.0 - NdotL), shadow.y * 0.1);
+    vec2 texel = 1.0 / vec2(textureSize(shadowMap, 0).xy);
+    int radius = int(shadow.w);
+    float lit = 0.0;
+    for (int x = -radius; x <= radius; ++x) {
+        for (int y = -radius; y <= radius; ++y) {
+            lit += texture(shadowMap, vec4(coords.xy + vec2(x, y) * texel, float(layer), coords.z - bias));
+        }
+    }
+    return lit / float((2 * radius + 1) * (2 * radius + 1));
+}
+
+// 1.0 when fully lit, light.shadow is (slot, depth bias, normal bias, pcf radius).
+float shadowFactor(Light light, vec3 fragPos, vec3 normal, vec3 L)
+{
+    int slot = int(light.shadow.x);
+    if (slot < 0) {
+        return 1.0;
+    }
+
+    int kind = int(light.position.w);
+    if (kind == LIGHT_DIRECTIONAL) {
+        float depth = dot(fragPos - viewPos, cameraForward.xyz);
+        int cascades = int(cameraForward.w);
+        for (int i = 0; i < cascades; ++i) {
+            if (depth < cascadeSplits[i]) {
+                return sampleShadow(cascadeShadowMap, cascadeMatrices[i], i, fragPos, normal, L, light.shadow);
+            }
+        }
+        return 1.0;
+    }
+    if (kind == LIGHT_SPOT) {
+        return sampleShadow(spotShadowMap, spotMatrices[slot], slot, fragPos, normal, L, light.shadow);
+    }
+    return 1.0;
+}
+#else
+float shadowFactor(Light light, vec3 fragPos, vec3 normal, vec3 L)
+{
+    return 1.0;
+}
+#endif
@@ -0,0 +1,47 @@
+// Material uniforms set by `Material::bind`, expects FragPos and TexCoord inputs to be declared.
+
+// Alpha modes, see `AlphaMode` in components.rs.
+const int ALPHA_OPAQUE = 0;
+const int ALPHA_MASK = 1;
+const int ALPHA_BLEND = 2;
+
+struct Material {
+    vec4 baseColor;
+    float metallic;
+    float roughness;
+    vec3 emissive;
+    float normalScale;
+    float occlusionStrength;
+    int alphaMode;
+    float alphaCutoff;
+
+    bool hasBaseColorTexture;
+    bool hasMetallicRoughnessTexture;
+    bool hasNormalTexture;
+    bool hasEmissiveTexture;
+    bool hasOcclusionTexture;
+
+    sampler2D baseColorTexture;
+    sampler2D metallicRoughnessTexture;
+    sampler2D normalTexture;
+    sampler2D emissiveTexture;
+    sampler2D occlusionTexture;
+};
+
+uniform Material material;
+
+// Tangent frame from screen-space derivatives, so meshes do not need tangent attributes.
+vec3 perturbNormal(vec3 normal, vec3 tangentNormal)
+{
+    vec3 dp1 = dFdx(FragPos);
+    vec3 dp2 = dFdy(FragPos);
+    vec2 duv1 = dFdx(TexCoord);
+    vec2 duv2 = dFdy(TexCoord);
+
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+    float invmax = inversesqrt(max(dot(tangent, tangent), dot(bitangent, bitangent)));
+    return normalize(mat3(tangent * invmax, bitangent * invmax, normal) * tangentNormal);
+}
@@ -0,0 +1,72 @@
+#version 410 core
+in vec3 FragPos;
+in vec3 Normal;
+in vec2 TexCoord;
+
+out vec4 FragColor;
+
+#include "material.glsl"
+#include "lighting.glsl"
+#include "brdf.glsl"
+
+void main()
+{
+    vec4 baseColor = material.baseColor;
+    if (material.hasBaseColorTexture) {
+        vec4 texel = texture(material.baseColorTexture, TexCoord);
+        baseColor *= vec4(pow(texel.rgb, vec3(2.2)), texel.a);
+    }
+    if (material.alphaMode == ALPHA_MASK && baseColor.a < material.alphaCutoff) {
+        discard;
+    }
+
+    float metallic = material.metallic;
+    float roughness = material.roughness;
+    if (material.hasMetallicRoughnessTexture) {
+        // glTF packs roughness in G and metalness in B.
+        vec4 texel = texture(material.metallicRoughnessTexture, TexCoord);
+        roughness *= texel.g;
+        metallic *= texel.b;
+    }
+    roughness = clamp(roughness, 0.04, 1.0);
+
+    vec3 N = normalize(Normal);
+    if (material.hasNormalTexture) {
+        vec3 tangentNormal = texture(material.normalTexture, TexCoord).xyz * 2.0 - 1.0;
+        tangentNormal.xy *= material.normalScale;
+        N = perturbNormal(N, normalize(tangentNormal));
+    }
+
+    vec3 V = normalize(viewPos - FragPos);
+    float NdotV = max(dot(N, V), 1e-4);
+    vec3 F0 = mix(vec3(0.04), baseColor.rgb, metallic);
+
+    vec3 Lo = vec3(0.0);
+    for (int i = 0; i < min(lightCount, MAX_LIGHTS); ++i) {
+        vec3 L;
+        vec3 radiance = lightRadiance(lights[i], FragPos, L) * shadowFactor(lights[i], FragPos, N, L);
+        vec3 H = normalize(V + L);
+        float NdotL = max(dot(N, L), 0.0);
+        float NdotH = max(dot(N, H), 0.0);
+
+        vec3 F = fresnelSchlick(max(dot(H, V), 0.0), F0);
+        vec3 specular = distributionGGX(NdotH, roughness) * geometrySmith(NdotV, NdotL, roughness) * F / (4.0 * NdotV * max(NdotL, 1e-4));
+        vec3 kD = (1.0 - F) * (1.0 - metallic);
+        Lo += (kD * baseColor.rgb / PI + specular) * radiance * NdotL;
+    }
+
+    float occlusion = 1.0;
+    if (material.hasOcclusionTexture) {
+        occlusion = mix(1.0, texture(material.occlusionTexture, TexCoord).r, material.occlusionStrength);
+    }
+    vec3 ambient = ambientLighting(N, V, NdotV, F0, baseColor.rgb, metallic, roughness) * occlusion;
+
+    vec3 emissive = material.emissive;
+    if (material.hasEmissiveTexture) {
+        emissive *= pow(texture(material.emissiveTexture, TexCoord).rgb, vec3(2.2));
+    }
+
+    // Linear HDR, exposure, tone mapping and gamma are applied by the post chain.
+    vec3 color = ambient + Lo + emissive;
+    FragColor = vec4(color, material.alphaMode == ALPHA_BLEND ? baseColor.a : 1.0);
+}
@@ -0,0 +1,12 @@
+#version 410 core
+in vec2 TexCoord;
+
+out vec4 FragColor;
+
+uniform sampler2D image;
+uniform float exposure;
+
+void main()
+{
+    FragColor = vec4(texture(image, TexCoord).rgb * exposure, 1.0);
+}
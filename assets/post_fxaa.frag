@@ -0,0 +1,43 @@
+#version 410 core
+in vec2 TexCoord;
+
+out vec4 FragColor;
+
+uniform sampler2D image;
+
+const float FXAA_SPAN_MAX = 8.0;
+const float FXAA_REDUCE_MUL = 1.0 / 8.0;
+const float FXAA_REDUCE_MIN = 1.0 / 128.0;
+
+float luma(vec3 color)
+{
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+// FXAA, blurs along the edge direction estimated from the luma of the diagonal neighbours.
+// Expects gamma corrected input, so run it after `Gamma`.
+void main()
+{
+    vec2 texel = 1.0 / vec2(textureSize(image, 0));
+    float lumaNW = luma(texture(image, TexCoord + vec2(-1.0, -1.0) * texel).rgb);
+    float lumaNE = luma(texture(image, TexCoord + vec2(1.0, -1.0) * texel).rgb);
+    float lumaSW = luma(texture(image, TexCoord + vec2(-1.0, 1.0) * texel).rgb);
+    float lumaSE = luma(texture(image, TexCoord + vec2(1.0, 1.0) * texel).rgb);
+    vec3 center = texture(image, TexCoord).rgb;
+    float lumaM = luma(center);
+
+    float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+    float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+    vec2 dir = vec2(-((lumaNW + lumaNE) - (lumaSW + lumaSE)), (lumaNW + lumaSW) - (lumaNE + lumaSE));
+    float dirReduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    float rcpDirMin = 1.0 / (min(abs(dir.x), abs(dir.y)) + dirReduce);
+    dir = clamp(dir * rcpDirMin, vec2(-FXAA_SPAN_MAX), vec2(FXAA_SPAN_MAX)) * texel;
+
+    vec3 rgbA = 0.5 * (texture(image, TexCoord + dir * (1.0 / 3.0 - 0.5)).rgb
+                     + texture(image, TexCoord + dir * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 rgbB = rgbA * 0.5 + 0.25 * (texture(image, TexCoord - dir * 0.5).rgb
+                                   + texture(image, TexCoord + dir * 0.5).rgb);
+    float lumaB = luma(rgbB);
+    FragColor = vec4((lumaB < lumaMin || lumaB > lumaMax) ? rgbA : rgbB, 1.0);
+}
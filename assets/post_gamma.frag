@@ -0,0 +1,13 @@
+#version 410 core
+in vec2 TexCoord;
+
+out vec4 FragColor;
+
+uniform sampler2D image;
+uniform float gamma;
+
+void main()
+{
+    vec3 color = max(texture(image, TexCoord).rgb, 0.0);
+    FragColor = vec4(pow(color, vec3(1.0 / gamma)), 1.0);
+}
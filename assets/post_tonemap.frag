@@ -0,0 +1,24 @@
+#version 410 core
+in vec2 TexCoord;
+
+out vec4 FragColor;
+
+// Tone mappers, see `ToneMapper` in post.rs.
+const int TONEMAP_REINHARD = 0;
+const int TONEMAP_ACES = 1;
+
+uniform sampler2D image;
+uniform int toneMapper;
+
+// Narkowicz's fit of the ACES filmic curve.
+vec3 aces(vec3 x)
+{
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main()
+{
+    vec3 color = texture(image, TexCoord).rgb;
+    color = toneMapper == TONEMAP_ACES ? aces(color) : color / (color + 1.0);
+    FragColor = vec4(color, 1.0);
+}
@@ -0,0 +1,16 @@
+#version 410 core
+in vec2 TexCoord;
+
+out vec4 FragColor;
+
+uniform sampler2D image;
+uniform float strength;
+uniform float radius;
+
+void main()
+{
+    // Darkens towards the corners, starting at `radius` from the center (0.5 reaches the edges).
+    float distance = length(TexCoord - 0.5);
+    float falloff = smoothstep(radius, radius + 0.35, distance);
+    FragColor = vec4(texture(image, TexCoord).rgb * (1.0 - strength * falloff), 1.0);
+}
@@ -0,0 +1,14 @@
+#version 410 core
+layout (location = 0) in vec3 aPos;
+
+out vec3 Direction;
+
+#include "camera.glsl"
+
+void main()
+{
+    Direction = aPos;
+    // Drop the translation so the sky stays at infinity, and force depth to the far plane.
+    vec4 position = projection * mat4(mat3(view)) * vec4(aPos, 1.0);
+    gl_Position = position.xyww;
+}
@@ -0,0 +1,64 @@
+#version 410 core
+in vec3 FragPos;
+in vec3 Normal;
+in vec2 TexCoord;
+
+out vec4 FragColor;
+
+// Layers are blended by world height and slope (1 - normal.y), or by the splat map when present.
+uniform int layerCount;
+uniform sampler2D layerTextures[MAX_TERRAIN_LAYERS];
+uniform float layerTiling[MAX_TERRAIN_LAYERS];
+uniform vec3 layerHeight[MAX_TERRAIN_LAYERS]; // min, max, blend width
+uniform vec3 layerSlope[MAX_TERRAIN_LAYERS];  // min, max, blend width
+
+uniform bool useSplatMap;
+uniform sampler2D splatMap;
+uniform vec4 splatRect; // world xz origin, world xz size
+
+#include "lighting.glsl"
+
+float band(float value, vec3 range)
+{
+    float blend = max(range.z, 1e-4);
+    return smoothstep(range.x - blend, range.x, value) * (1.0 - smoothstep(range.y, range.y + blend, value));
+}
+
+void main()
+{
+    vec3 normal = normalize(Normal);
+    float slope = 1.0 - normal.y;
+
+    vec4 splat = texture(splatMap, (FragPos.xz - splatRect.xy) / splatRect.zw);
+
+    vec3 albedo = vec3(0.0);
+    float totalWeight = 0.0;
+    // Constant bound so the loop unrolls, some drivers (llvmpipe) crash indexing samplers otherwise.
+    for (int i = 0; i < MAX_TERRAIN_LAYERS; ++i) {
+        if (i >= layerCount) {
+            break;
+        }
+        float weight = useSplatMap
+            ? splat[i]
+            : band(FragPos.y, layerHeight[i]) * band(slope, layerSlope[i]);
+        albedo += pow(texture(layerTextures[i], FragPos.xz * layerTiling[i]).rgb, vec3(2.2)) * weight;
+        totalWeight += weight;
+    }
+    if (totalWeight > 1e-4) {
+        albedo /= totalWeight;
+    } else {
+        albedo = pow(texture(layerTextures[0], FragPos.xz * layerTiling[0]).rgb, vec3(2.2));
+    }
+
+    vec3 viewDir = normalize(viewPos - FragPos);
+    vec3 lighting = vec3(0.2);
+    for (int i = 0; i < min(lightCount, MAX_LIGHTS); ++i) {
+        vec3 lightDir;
+        vec3 radiance = lightRadiance(lights[i], FragPos, lightDir) * shadowFactor(lights[i], FragPos, normal, lightDir);
+        vec3 halfway = normalize(lightDir + viewDir);
+        lighting += max(dot(normal, lightDir), 0.0) * radiance;
+        lighting += 0.1 * pow(max(dot(normal, halfway), 0.0), 16.0) * radiance;
+    }
+
+    FragColor = vec4(lighting * albedo, 1.0);
+}
@@ -0,0 +1,80 @@
+use crate::graphics::Graphics;
+use crate::shader::{load_shader, Shader, TextureUnit};
+use crate::render_state;
+
+// Makes emissive surfaces glow. Extracts everything brighter than a threshold, blurs it at half
+// resolution and adds it back on top of the source. Used by the `PostEffect::Bloom` step.
+pub struct Bloom {
+    width: u32,
+    height: u32,
+    blur_fbos: [u32; 2],
+    blur_textures: [u32; 2],
+    extract: Shader,
+    blur: Shader,
+    composite: Shader,
+}
+
+impl Bloom {
+    pub fn new(width: u32, height: u32) -> Result<Self, String> {
+        let extract = load_shader("assets/fullscreen.vert", "assets/bloom_extract.frag")?;
+        let blur = load_shader("assets/fullscreen.vert", "assets/bloom_blur.frag")?;
+        let composite = load_shader("assets/fullscreen.vert", "assets/bloom_composite.frag")?;
+
+        let (blur_width, blur_height) = ((width / 2).max(1), (height / 2).max(1));
+        let blur_textures = [
+            Graphics::create_render_texture(blur_width, blur_height, gl::RGBA16F),
+            Graphics::create_render_texture(blur_width, blur_height, gl::RGBA16F),
+        ];
+        let blur_fbos = [
+            Graphics::create_framebuffer(&[blur_textures[0]], None)?,
+            Graphics::create_framebuffer(&[blur_textures[1]], None)?,
+        ];
+
+        Ok(Self {
+            width,
+            height,
+            blur_fbos,
+            blur_textures,
+            extract,
+            blur,
+            composite,
+        })
+    }
+
+    // Draws `source` + bloom into `target`. Expects a fullscreen triangle VAO to be bound.
+    // Each blur pass is one horizontal and one vertical blur.
+    pub fn apply(&self, source: u32, target: u32, threshold: f32, intensity: f32, blur_passes: u32) {
+        unsafe {
+            gl::Viewport(0, 0, (self.width / 2).max(1) as i32, (self.height / 2).max(1) as i32);
+
+            self.extract.use_program();
+            self.extract.set_uniform("scene", TextureUnit(0));
+            self.extract.set_uniform("threshold", threshold);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.blur_fbos[0]);
+            render_state::bind_texture(0, gl::TEXTURE_2D, source);
+            render_state::draw_fullscreen_triangle();
+
+            // Ping-pong between the two half resolution targets, ending in blur_textures[0].
+            self.blur.use_program();
+            self.blur.set_uniform("image", TextureUnit(0));
+            for _ in 0..blur_passes {
+                for (from, horizontal) in [(0, true), (1, false)] {
+                    self.blur.set_uniform("horizontal", horizontal);
+                    gl::BindFramebuffer(gl::FRAMEBUFFER, self.blur_fbos[1 - from]);
+                    render_state::bind_texture(0, gl::TEXTURE_2D, self.blur_textures[from]);
+                    render_state::draw_fullscreen_triangle();
+                }
+            }
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, target);
+            gl::Viewport(0, 0, self.width as i32, self.height as i32);
+            self.composite.use_program();
+            self.composite.set_uniform("scene", TextureUnit(0));
+            self.composite.set_uniform("bloom", TextureUnit(1));
+            self.composite.set_uniform("intensity", intensity);
+            render_state::bind_texture(0, gl::TEXTURE_2D, source);
+            render_state::bind_texture(1, gl::TEXTURE_2D, self.blur_textures[0]);
+            render_state::draw_fullscreen_triangle();
+        }
+    }
+}
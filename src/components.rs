@@ -1,6 +1,8 @@
-use glam::{Mat4, Vec2, Vec3};
+use glam::{Mat4, Vec2, Vec3, Vec4};
 use flecs_ecs::prelude::*;
-use crate::graphics::Shader;
+use crate::culling::{Aabb, Frustum};
+use crate::shader::Shader;
+use crate::terrain::Heightfield;
 // --- Component Struct Definitions ---
 
 
@@ -28,17 +30,129 @@ pub struct Mesh {
     pub vao: u32,
     pub vbo: u32,
     pub ebo: u32,
+    // Local space bounds of `vertices`.
+    pub bounds: Aabb,
 }
 
-#[derive(Component, Clone, Copy, Debug)]
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
 pub struct Texture {
     pub id: u32,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    // Fragments with alpha below the cutoff are discarded.
+    Mask(f32),
+    Blend,
+}
+
+// Metallic-roughness surface description bound by the render system for every mesh.
+// Factors multiply the matching texture when one is set.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    pub base_color: Vec4,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: Vec3,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    pub alpha_mode: AlphaMode,
+    pub base_color_texture: Option<Texture>,
+    pub metallic_roughness_texture: Option<Texture>,
+    pub normal_texture: Option<Texture>,
+    pub emissive_texture: Option<Texture>,
+    pub occlusion_texture: Option<Texture>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color: Vec4::ONE,
+            metallic: 0.0,
+            roughness: 0.5,
+            emissive: Vec3::ZERO,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            alpha_mode: AlphaMode::Opaque,
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            emissive_texture: None,
+            occlusion_texture: None,
+        }
+    }
+}
+
+// One texture of a terrain material, applied within a height and slope band.
+// Ranges are (min, max, blend width); slope is 1 - normal.y, so 0 is flat and 1 is vertical.
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainLayer {
+    pub texture: Texture,
+    // Texture repeats per world unit.
+    pub tiling: f32,
+    pub height: Vec3,
+    pub slope: Vec3,
+}
+
+// Explicit layer weights (one per RGBA channel) covering a world-space rectangle of the terrain.
+#[derive(Clone, Copy, Debug)]
+pub struct SplatMap {
+    pub texture: Texture,
+    pub origin: Vec2,
+    pub size: Vec2,
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct TerrainMaterial {
+    // At most `MAX_TERRAIN_LAYERS`, in splat map channel order.
+    pub layers: Vec<TerrainLayer>,
+    pub splat_map: Option<SplatMap>,
+}
+
+// CPU copy of a terrain mesh's heights, used for world-space height, normal and raycast queries.
+#[derive(Component, Clone, Debug)]
+pub struct Terrain {
+    pub heightfield: Heightfield,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    // Shines along the entity's -Z axis from infinitely far away.
+    Directional,
+    Point { range: f32 },
+    // Cone along the entity's -Z axis, angles are half-angles in degrees.
+    Spot { range: f32, inner_angle: f32, outer_angle: f32 },
+}
+
+// Shadow casting parameters of a directional or spot light (point lights cast no shadows).
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowSettings {
+    // Constant depth offset in shadow map depth units, scaled up on grazing angles.
+    pub depth_bias: f32,
+    // World-space offset of the receiver along its normal.
+    pub normal_bias: f32,
+    // PCF kernel is (2 * radius + 1)^2 taps.
+    pub pcf_radius: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            depth_bias: 0.002,
+            normal_bias: 0.05,
+            pcf_radius: 1,
+        }
+    }
+}
+
+// Lights take their position and direction from the entity's (Transform, Global).
 #[derive(Component, Clone, Copy, Debug)]
 pub struct Light {
     pub color: Vec3,
     pub intensity: f32,
+    pub kind: LightKind,
+    pub shadow: Option<ShadowSettings>,
 }
 
 #[derive(Component, Clone, Copy, Debug)]
@@ -54,16 +168,32 @@ pub struct Emission {
     pub radius: f32,
 }
 
+impl Emission {
+    // The orb's radius plus the distance d at which its inverse square light, intensity / d²,
+    // falls to an absolute 0.01.
+    pub fn light_range(&self) -> f32 {
+        self.radius + 10.0 * self.intensity.max(0.0).sqrt()
+    }
+}
+
 #[derive(Component, Clone, Copy, Debug)]
 pub struct Skybox {
     pub cubemap_id: u32,
 }
 
+// Image based lighting maps of the scene, see ibl.rs. Set as a singleton on the world.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Environment {
+    pub irradiance_map: u32,
+    pub prefilter_map: u32,
+    pub brdf_lut: u32,
+}
+
 // --- Tag Components ---
-#[derive(Component,Clone, Copy, Debug)]
+#[derive(Component, Clone, Debug)]
 pub struct PBRShader(pub Shader);
 
-#[derive(Component,Clone, Copy, Debug)]
+#[derive(Component, Clone, Debug)]
 pub struct EmissiveShader(pub Shader);
 
 // --- Singleton Resources ---
@@ -72,12 +202,70 @@ pub struct ActiveCameraData {
     pub pos: Vec3,
     pub view: Mat4,
     pub projection: Mat4,
+    pub frustum: Frustum,
+}
+
+// Draws issued and skipped by frustum culling since the last `Ecs::take_render_stats`.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    pub drawn: u32,
+    pub culled: u32,
+    // GL draw calls the drawn meshes took, lower than `drawn` when instancing kicks in.
+    pub draw_calls: u32,
 }
 
+// Which path draws opaque `Material` meshes, chosen at startup. With `Deferred` the forward
+// Render System only draws terrain and alpha blended meshes, see `DeferredRenderer`.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub enum RenderPath {
+    Forward,
+    Deferred,
+}
+
+// Lights gathered by the Light System and uploaded to `ubo` once per frame.
 #[derive(Component, Debug)]
 pub struct ActiveLightData {
-    pub pos: Vec3,
-    pub color: Vec3,
+    pub block: LightBlock,
+    pub ubo: u32,
+}
+
+pub const MAX_LIGHTS: usize = 16;
+pub const MAX_CASCADES: usize = 4;
+pub const MAX_SPOT_SHADOWS: usize = 4;
+
+// std140 layout of the `Lights` uniform block in the shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GpuLight {
+    // xyz, w = kind (0 directional, 1 point, 2 spot)
+    pub position: [f32; 4],
+    // xyz, w = range
+    pub direction: [f32; 4],
+    // rgb premultiplied by intensity
+    pub color: [f32; 4],
+    // x = cos(inner angle), y = cos(outer angle)
+    pub cone: [f32; 4],
+    // x = shadow slot (-1 for none), y = depth bias, z = normal bias, w = pcf radius
+    pub shadow: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LightBlock {
+    pub lights: [GpuLight; MAX_LIGHTS],
+    pub count: i32,
+    pub _padding: [i32; 3],
+}
+
+// std140 layout of the `Camera` uniform block, uploaded once per frame by the Camera System.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CameraBlock {
+    pub view: Mat4,
+    pub projection: Mat4,
+    pub inverse_view_projection: Mat4,
+    // xyz, w unused.
+    pub position: [f32; 4],
 }
 
 #[repr(C)]
@@ -0,0 +1,191 @@
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+use crate::components::Vertex;
+
+// Axis aligned bounding box, computed for every mesh by `Graphics::create_mesh`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn from_vertices(vertices: &[Vertex]) -> Self {
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+        for vertex in vertices {
+            min = min.min(vertex.position);
+            max = max.max(vertex.position);
+        }
+        if vertices.is_empty() {
+            return Aabb::default();
+        }
+        Aabb { min, max }
+    }
+
+    // Box around this box after `transform`, without transforming all 8 corners (Arvo).
+    pub fn transformed(&self, transform: &Mat4) -> Aabb {
+        let center = transform.transform_point3((self.min + self.max) * 0.5);
+        let extent = (self.max - self.min) * 0.5;
+        let axes = [transform.x_axis.xyz(), transform.y_axis.xyz(), transform.z_axis.xyz()];
+        let world_extent = axes[0].abs() * extent.x + axes[1].abs() * extent.y + axes[2].abs() * extent.z;
+        Aabb { min: center - world_extent, max: center + world_extent }
+    }
+}
+
+// Six planes (xyz = inward normal, w = distance) of a view projection, updated by the Camera System.
+// The default frustum has degenerate planes and lets everything through.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Frustum {
+    pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+    // Gribb/Hartmann plane extraction from a GL style clip space matrix.
+    pub fn from_view_projection(m: &Mat4) -> Self {
+        let rows = [m.row(0), m.row(1), m.row(2), m.row(3)];
+        let planes = [
+            rows[3] + rows[0],
+            rows[3] - rows[0],
+            rows[3] + rows[1],
+            rows[3] - rows[1],
+            rows[3] + rows[2],
+            rows[3] - rows[2],
+        ];
+        Frustum {
+            planes: planes.map(|plane| plane / plane.xyz().length().max(f32::EPSILON)),
+        }
+    }
+
+    // False only when the box is fully outside one of the planes.
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // Corner of the box furthest along the plane normal.
+            let corner = Vec3::select(plane.xyz().cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            plane.xyz().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Quat;
+    use super::*;
+
+    fn aabb(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    fn assert_aabb_eq(actual: Aabb, expected: Aabb) {
+        assert!(
+            actual.min.abs_diff_eq(expected.min, 1e-4) && actual.max.abs_diff_eq(expected.max, 1e-4),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    // Box around all 8 transformed corners, what `transformed` must match.
+    fn transformed_corners(aabb: &Aabb, transform: &Mat4) -> Aabb {
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+        for i in 0..8 {
+            let corner = Vec3::select(glam::BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0), aabb.max, aabb.min);
+            let point = transform.transform_point3(corner);
+            min = min.min(point);
+            max = max.max(point);
+        }
+        Aabb { min, max }
+    }
+
+    // 90° field of view looking down -Z from the origin, so the side planes are x = ±z and y = ±z.
+    fn frustum() -> Frustum {
+        let projection = Mat4::perspective_rh_gl(90f32.to_radians(), 1.0, 0.1, 100.0);
+        let view = Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y);
+        Frustum::from_view_projection(&(projection * view))
+    }
+
+    fn cube_at(center: Vec3) -> Aabb {
+        aabb(center - 0.5, center + 0.5)
+    }
+
+    #[test]
+    fn transformed_by_identity_is_unchanged() {
+        let unit = aabb(Vec3::new(-1.0, 0.0, 2.0), Vec3::new(1.0, 3.0, 4.0));
+        assert_aabb_eq(unit.transformed(&Mat4::IDENTITY), unit);
+    }
+
+    #[test]
+    fn transformed_translates_and_scales() {
+        let unit = aabb(Vec3::splat(-1.0), Vec3::splat(1.0));
+        let transform = Mat4::from_scale_rotation_translation(Vec3::new(2.0, 3.0, 4.0), Quat::IDENTITY, Vec3::new(10.0, 0.0, -5.0));
+        assert_aabb_eq(unit.transformed(&transform), aabb(Vec3::new(8.0, -3.0, -9.0), Vec3::new(12.0, 3.0, -1.0)));
+    }
+
+    #[test]
+    fn transformed_rotated_grows_to_fit() {
+        let unit = aabb(Vec3::splat(-1.0), Vec3::splat(1.0));
+        let transform = Mat4::from_rotation_z(45f32.to_radians());
+        let half_diagonal = 2f32.sqrt();
+        assert_aabb_eq(
+            unit.transformed(&transform),
+            aabb(Vec3::new(-half_diagonal, -half_diagonal, -1.0), Vec3::new(half_diagonal, half_diagonal, 1.0)),
+        );
+    }
+
+    #[test]
+    fn transformed_matches_transforming_corners() {
+        let boxed = aabb(Vec3::new(-1.0, 0.5, -2.0), Vec3::new(3.0, 1.5, 0.0));
+        let transform = Mat4::from_scale_rotation_translation(
+            Vec3::new(0.5, 2.0, 1.5),
+            Quat::from_euler(glam::EulerRot::YXZ, 0.7, -0.3, 1.2),
+            Vec3::new(4.0, -2.0, 7.0),
+        );
+        assert_aabb_eq(boxed.transformed(&transform), transformed_corners(&boxed, &transform));
+    }
+
+    #[test]
+    fn frustum_contains_box_in_front() {
+        assert!(frustum().intersects(&cube_at(Vec3::new(0.0, 0.0, -10.0))));
+    }
+
+    #[test]
+    fn frustum_rejects_box_outside_each_plane() {
+        let frustum = frustum();
+        let outside = [
+            ("left", Vec3::new(-12.0, 0.0, -10.0)),
+            ("right", Vec3::new(12.0, 0.0, -10.0)),
+            ("bottom", Vec3::new(0.0, -12.0, -10.0)),
+            ("top", Vec3::new(0.0, 12.0, -10.0)),
+            ("near", Vec3::new(0.0, 0.0, 1.0)),
+            ("far", Vec3::new(0.0, 0.0, -120.0)),
+        ];
+        for (plane, center) in outside {
+            assert!(!frustum.intersects(&cube_at(center)), "box outside the {} plane was kept", plane);
+        }
+    }
+
+    #[test]
+    fn frustum_keeps_box_straddling_a_plane() {
+        let frustum = frustum();
+        // Crosses the left plane x = z = -10.
+        assert!(frustum.intersects(&aabb(Vec3::new(-15.0, -1.0, -11.0), Vec3::new(-5.0, 1.0, -9.0))));
+        // Crosses the near and far planes.
+        assert!(frustum.intersects(&aabb(Vec3::new(-1.0, -1.0, -150.0), Vec3::new(1.0, 1.0, 1.0))));
+    }
+
+    #[test]
+    fn frustum_tests_rotated_box() {
+        let frustum = frustum();
+        // A long thin box left of the view, only reaching in once turned across it.
+        let thin = aabb(Vec3::new(-0.5, -5.0, -0.5), Vec3::new(0.5, 5.0, 0.5));
+        let position = Mat4::from_translation(Vec3::new(-13.0, 0.0, -10.0));
+        assert!(!frustum.intersects(&thin.transformed(&position)));
+        let turned = position * Mat4::from_rotation_z(90f32.to_radians());
+        assert!(frustum.intersects(&thin.transformed(&turned)));
+    }
+
+    #[test]
+    fn default_frustum_keeps_everything() {
+        assert!(Frustum::default().intersects(&cube_at(Vec3::new(0.0, 0.0, 1000.0))));
+    }
+}
@@ -0,0 +1,124 @@
+use flecs_ecs::prelude::*;
+use crate::components::*;
+use crate::ecs::Ecs;
+use crate::graphics::Graphics;
+use crate::shader::{load_shader, load_shader_with, Shader, TextureUnit};
+use crate::shader_preprocessor::Defines;
+use crate::instancing::InstanceBatches;
+use crate::render_state;
+
+// Deferred path for scenes with many lights. Opaque and alpha masked `Material` meshes are
+// written to a G-buffer, then one fullscreen pass lights every pixel with all `Light`s.
+// Terrain and alpha blended meshes are left to the forward Render System, which runs after
+// `render` and depth tests against the G-buffer depth.
+pub struct DeferredRenderer {
+    width: u32,
+    height: u32,
+    fbo: u32,
+    // Albedo + occlusion, normal, metallic/roughness, emissive.
+    targets: [u32; 4],
+    depth: u32,
+    vao: u32,
+    geometry: Shader,
+    lighting: Shader,
+    batches: InstanceBatches,
+    meshes: Query<(&'static Mesh, &'static (Transform, Global), Option<&'static Material>)>,
+}
+
+impl DeferredRenderer {
+    pub fn new(world: &Ecs, width: u32, height: u32) -> Result<Self, String> {
+        let geometry = load_shader("assets/pbr.vert", "assets/gbuffer.frag")?;
+        let lighting = load_shader_with("assets/fullscreen.vert", "assets/deferred_lighting.frag", &Defines::new().flag("SHADOWS"))?;
+
+        let targets = [
+            Graphics::create_render_texture(width, height, gl::RGBA8),
+            Graphics::create_render_texture(width, height, gl::RGBA16F),
+            Graphics::create_render_texture(width, height, gl::RGBA8),
+            Graphics::create_render_texture(width, height, gl::RGBA16F),
+        ];
+        let depth = Graphics::create_depth_texture(width, height);
+        let fbo = Graphics::create_framebuffer(&targets, Some(depth))?;
+
+        lighting.use_program();
+        for (unit, name) in ["gAlbedo", "gNormal", "gMaterial", "gEmissive", "gDepth"].iter().enumerate() {
+            lighting.set_uniform(name, TextureUnit(unit as u32));
+        }
+
+        let mut vao = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+        }
+
+        Ok(Self {
+            width,
+            height,
+            fbo,
+            targets,
+            depth,
+            vao,
+            geometry,
+            lighting,
+            batches: InstanceBatches::new(),
+            meshes: world
+                .world
+                .query::<(&Mesh, &(Transform, Global), Option<&Material>)>()
+                .with::<PBRShader>()
+                .without::<TerrainMaterial>()
+                .build(),
+        })
+    }
+
+    // Runs the geometry and lighting passes into the framebuffer bound by the caller.
+    // Call after `Graphics::begin_frame` and before the forward Render System.
+    pub fn render(&mut self, world: &Ecs) {
+        let (frustum, eye) = world.world.map::<&ActiveCameraData, _>(|camera| (camera.frustum, camera.pos));
+        let mut stats = RenderStats::default();
+        let default_material = Material::default();
+
+        let mut target = 0;
+        unsafe {
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut target);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::Viewport(0, 0, self.width as i32, self.height as i32);
+            gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+            gl::Enable(gl::DEPTH_TEST);
+        }
+
+        self.meshes.each(|(mesh, world, material)| {
+            let material = material.unwrap_or(&default_material);
+            if material.alpha_mode == AlphaMode::Blend {
+                return;
+            }
+            if !frustum.intersects(&mesh.bounds.transformed(&world.0)) {
+                stats.culled += 1;
+                return;
+            }
+            stats.drawn += 1;
+            self.batches.push(mesh, &self.geometry, material, world.0);
+        });
+        stats.draw_calls = self.batches.flush(eye);
+
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, target as u32);
+            gl::Disable(gl::BLEND);
+            // The lighting pass writes the G-buffer depth through gl_FragDepth.
+            gl::DepthFunc(gl::ALWAYS);
+        }
+        world.world.map::<&mut RenderStats, _>(|total| {
+            total.drawn += stats.drawn;
+            total.culled += stats.culled;
+            total.draw_calls += stats.draw_calls;
+        });
+
+        self.lighting.use_program();
+        for (unit, texture) in self.targets.iter().chain([&self.depth]).enumerate() {
+            render_state::bind_texture(unit as u32, gl::TEXTURE_2D, *texture);
+        }
+        render_state::bind_vertex_array(self.vao);
+        render_state::draw_fullscreen_triangle();
+        unsafe {
+            gl::DepthFunc(gl::LESS);
+        }
+    }
+}
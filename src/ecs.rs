@@ -1,12 +1,16 @@
-use std::ffi::CString;
-use std::ptr;
+use std::cell::RefCell;
+use std::rc::Rc;
 use super::components::*;
 use flecs_ecs::prelude::*;
 use flecs_ecs::prelude::system::System;
 use gl::types::GLsizei;
 use glam::{Mat4, Quat, Vec3, Vec4, Vec4Swizzles};
+use crate::culling::Frustum;
 use crate::graphics;
-use crate::graphics::{Graphics, Shader};
+use crate::graphics::Graphics;
+use crate::shader::{Shader, TextureUnit};
+use crate::instancing::InstanceBatches;
+use crate::render_state;
 
 pub struct Ecs {
     pub world:World,
@@ -21,19 +25,36 @@ impl Ecs {
             pos: Default::default(),
             view: Default::default(),
             projection: Default::default(),
+            frustum: Default::default(),
         });
+        world.set(RenderStats::default());
+        world.set(ActiveLightData {
+            block: LightBlock::default(),
+            ubo: 0,
+        });
+        world.set(RenderPath::Forward);
         Self {world: world}
     }
-    pub fn create_system(&self) -> (System, System, System)
+    pub fn create_system(&self) -> (System, System, System, System)
     {
+        let camera_ubo = Graphics::create_uniform_buffer::<CameraBlock>(graphics::CAMERA_BINDING);
         let csys=   self.world
             .system_named::<(&(Transform,Global),&Camera, &mut ActiveCameraData)>("Camera System").term_at(2).singleton()
-            .each(|(world, camera, active_camera)| {
+            .each(move |(world, camera, active_camera)| {
 
                 active_camera.projection = camera.projection;
                 active_camera.view =  world.0.inverse();
                 active_camera.pos =  (world.0 * Vec4{x:0.0,y:0.0,z:0.0,w:1.0}).xyz();
+                let view_projection = active_camera.projection * active_camera.view;
+                active_camera.frustum = Frustum::from_view_projection(&view_projection);
 
+                // Shaders read the camera from the `Camera` block instead of per draw uniforms.
+                Graphics::update_uniform_buffer(camera_ubo, &CameraBlock {
+                    view: active_camera.view,
+                    projection: active_camera.projection,
+                    inverse_view_projection: view_projection.inverse(),
+                    position: active_camera.pos.extend(1.0).to_array(),
+                });
             });
 
         let usys=   self.world
@@ -47,43 +68,191 @@ impl Ecs {
 
             });
 
+        let light_ubo = Graphics::create_uniform_buffer::<LightBlock>(graphics::LIGHTS_BINDING);
+        let light_query = self.world.new_query::<(&Light, &(Transform, Global))>();
+        let emission_query = self.world.new_query::<(&Emission, &(Transform, Global))>();
+        let lsys= self.world
+            .system_named::<&mut ActiveLightData>("Light System").term_at(0).singleton()
+            .each(move |active_lights| {
+                active_lights.ubo = light_ubo;
+                active_lights.block = LightBlock::default();
+                let block = &mut active_lights.block;
+                // Shadow slots: the first shadowed directional light gets the cascades,
+                // shadowed spot lights get a spot shadow layer each while they last.
+                let mut has_cascades = false;
+                let mut spot_shadows = 0;
+                light_query.each(|(light, world)| {
+                    if block.count as usize >= MAX_LIGHTS {
+                        return;
+                    }
+                    let position = world.0.transform_point3(Vec3::ZERO);
+                    let direction = world.0.transform_vector3(Vec3::NEG_Z).normalize();
+                    let color = light.color * light.intensity;
+                    let (kind, range, inner, outer) = match light.kind {
+                        LightKind::Directional => (0.0, 0.0, 0.0, 0.0),
+                        LightKind::Point { range } => (1.0, range, 0.0, 0.0),
+                        LightKind::Spot { range, inner_angle, outer_angle } => (2.0, range, inner_angle, outer_angle),
+                    };
+                    block.lights[block.count as usize] = GpuLight {
+                        position: [position.x, position.y, position.z, kind],
+                        direction: [direction.x, direction.y, direction.z, range],
+                        color: [color.x, color.y, color.z, 1.0],
+                        cone: [inner.to_radians().cos(), outer.to_radians().cos(), 0.0, 0.0],
+                        shadow: [-1.0, 0.0, 0.0, 0.0],
+                    };
+                    if let Some(shadow) = light.shadow {
+                        let slot = match light.kind {
+                            LightKind::Directional if !has_cascades => {
+                                has_cascades = true;
+                                Some(0)
+                            }
+                            LightKind::Spot { .. } if spot_shadows < MAX_SPOT_SHADOWS => {
+                                spot_shadows += 1;
+                                Some(spot_shadows - 1)
+                            }
+                            _ => None,
+                        };
+                        if let Some(slot) = slot {
+                            block.lights[block.count as usize].shadow = [slot as f32, shadow.depth_bias, shadow.normal_bias, shadow.pcf_radius as f32];
+                        }
+                    }
+                    block.count += 1;
+                });
+                // Emissive orbs light their surroundings as unshadowed point lights.
+                emission_query.each(|(emission, world)| {
+                    if block.count as usize >= MAX_LIGHTS {
+                        return;
+                    }
+                    let position = world.0.transform_point3(emission.center_position);
+                    let color = emission.orb_color * emission.intensity;
+                    block.lights[block.count as usize] = GpuLight {
+                        position: [position.x, position.y, position.z, 1.0],
+                        direction: [0.0, 0.0, 0.0, emission.light_range()],
+                        color: [color.x, color.y, color.z, 1.0],
+                        cone: [0.0; 4],
+                        shadow: [-1.0, 0.0, 0.0, 0.0],
+                    };
+                    block.count += 1;
+                });
+                Graphics::update_uniform_buffer(light_ubo, block);
+            });
+
+        // Meshes are only collected while iterating, the run callback draws them in batches.
+        let default_material = Material::default();
+        let batches = Rc::new(RefCell::new(InstanceBatches::new()));
+        let pending = batches.clone();
         let rsys= self.world
-            .system_named::<(&(Transform,Global), &Mesh, Option<&Texture>, &mut PBRShader, &mut ActiveCameraData)>("Render System").term_at(4).singleton()
-            .each(|(world, mesh,texture,pbr, camera)| {
+            .system_named::<(&(Transform,Global), &Mesh, Option<&Material>, Option<&TerrainMaterial>, &mut PBRShader, &mut ActiveCameraData, &RenderPath, &mut RenderStats)>("Render System").term_at(5).singleton().term_at(6).singleton().term_at(7).singleton()
+            .run_each(move |mut it| {
+                while it.next() {
+                    it.each();
+                }
+                let eye = it.world().map::<&ActiveCameraData, _>(|camera| camera.pos);
+                let draw_calls = batches.borrow_mut().flush(eye);
+                it.world().map::<&mut RenderStats, _>(|stats| stats.draw_calls += draw_calls);
+            }, move |(world, mesh,material,terrain_material,pbr, camera, render_path, stats)| {
+                if *render_path == RenderPath::Deferred && terrain_material.is_none()
+                    && material.is_none_or(|material| material.alpha_mode != AlphaMode::Blend) {
+                    // Already drawn by the DeferredRenderer.
+                    return;
+                }
+                if !camera.frustum.intersects(&mesh.bounds.transformed(&world.0)) {
+                    stats.culled += 1;
+                    return;
+                }
+                stats.drawn += 1;
 
+                let terrain_material = match (material, terrain_material) {
+                    (None, Some(terrain_material)) => terrain_material,
+                    _ => {
+                        pending.borrow_mut().push(mesh, &pbr.0, material.unwrap_or(&default_material), world.0);
+                        return;
+                    }
+                };
+                // Every terrain chunk has a mesh of its own, so terrain is not batched.
+                stats.draw_calls += 1;
                 pbr.0.use_program();
-                pbr.0.set_uniform_mat4("view",&camera.view);
-                pbr.0.set_uniform_mat4("projection",&camera.projection);
-                pbr.0.set_uniform_mat4("model",&world.0);
+                pbr.0.set_uniform("model", world.0);
+                terrain_material.bind(&pbr.0);
+                render_state::bind_vertex_array(mesh.vao);
+                render_state::draw_elements(mesh.indices.len() as GLsizei);
+            });
+        (usys,rsys, csys, lsys)
+    }
 
-                pbr.0.set_uniform_vec3("lightPos", &Vec3::ONE);
-                pbr.0.set_uniform_vec3("lightColor", &Vec3::ONE);
-                pbr.0.set_uniform_vec3("viewPos", &camera.pos);
+    // Draws the `Skybox` singleton after opaque geometry. Depth is forced to the far plane,
+    // so the sky only fills pixels no geometry covered.
+    pub fn create_skybox_system(&self, shader: Shader) -> System<'_> {
+        let cube = Graphics::create_skybox_mesh();
+        self.world
+            .system_named::<&Skybox>("Skybox System").term_at(0).singleton()
+            .each(move |skybox| {
+                shader.use_program();
+                shader.set_uniform("skybox", TextureUnit(0));
                 unsafe {
-                    let c_name_has_tex = CString::new("has_texture").unwrap();
-                    let loc_has_tex = gl::GetUniformLocation(pbr.0.id, c_name_has_tex.as_ptr());
-                    if let Some(texture) = texture
-                    {
-                        gl::Uniform1i(loc_has_tex, 1); // 0 for false
-                    }
-                    else
-                    {
-                        gl::Uniform1i(loc_has_tex, 0);
-                        let c_name_def_col = CString::new("default_color").unwrap();
-                        let loc_def_col = gl::GetUniformLocation(pbr.0.id, c_name_def_col.as_ptr());
-                        gl::Uniform3f(loc_def_col, 0.8, 0.5, 0.2); // An orange col// 0 for false
-                    }
+                    gl::DepthFunc(gl::LEQUAL);
+                    gl::Disable(gl::BLEND);
+                }
+                render_state::bind_texture(0, gl::TEXTURE_CUBE_MAP, skybox.cubemap_id);
+                render_state::bind_vertex_array(cube.vao);
+                render_state::draw_elements(cube.indices.len() as GLsizei);
+                unsafe {
+                    gl::DepthFunc(gl::LESS);
+                }
+            })
+    }
 
-                    //
-                    gl::BindVertexArray(mesh.vao);
-                    gl::DrawElements(gl::TRIANGLES, mesh.indices.len() as GLsizei, gl::UNSIGNED_INT, ptr::null());
-                    gl::BindVertexArray(0);
+    // Draws entities with an `Emission` using their `EmissiveShader`, unlit and unclamped
+    // so a `Bloom` pass can make them glow.
+    pub fn create_emissive_system(&self) -> System<'_> {
+        self.world
+            .system_named::<(&(Transform,Global), &Mesh, &Emission, &EmissiveShader)>("Emissive System")
+            .each(|(world, mesh, emission, shader)| {
+                shader.0.use_program();
+                shader.0.set_uniform("model", world.0);
+                shader.0.set_uniform("emission.color", emission.orb_color);
+                shader.0.set_uniform("emission.intensity", emission.intensity);
+                unsafe {
+                    gl::Disable(gl::BLEND);
                 }
-            });
-        (usys,rsys, csys)
+                render_state::bind_vertex_array(mesh.vao);
+                render_state::draw_elements(mesh.indices.len() as GLsizei);
+            })
+    }
+
+    pub fn add_emission(&self, e: Entity, emission: Emission, shader: Shader) {
+        e.entity_view(&self.world).set(emission).set(EmissiveShader(shader));
+    }
+
+    // Binds the `Environment` singleton to the texture units the PBR shader reads ambient light from.
+    pub fn create_environment_system(&self) -> System<'_> {
+        self.world
+            .system_named::<&Environment>("Environment System").term_at(0).singleton()
+            .each(|environment| {
+                render_state::bind_texture(graphics::IRRADIANCE_UNIT, gl::TEXTURE_CUBE_MAP, environment.irradiance_map);
+                render_state::bind_texture(graphics::PREFILTER_UNIT, gl::TEXTURE_CUBE_MAP, environment.prefilter_map);
+                render_state::bind_texture(graphics::BRDF_LUT_UNIT, gl::TEXTURE_2D, environment.brdf_lut);
+            })
+    }
+
+    pub fn set_environment(&self, environment: Environment) {
+        self.world.set(environment);
+    }
+
+    // Returns the draw counters of the frames since the last call and resets them.
+    pub fn take_render_stats(&self) -> RenderStats {
+        self.world.map::<&mut RenderStats, _>(std::mem::take)
     }
 
-    pub fn create_entity(&mut self, name: &str, pos: Vec3, scale: Vec3, rot_euler_deg: Vec3, parent: Option<Entity>) -> Entity {
+    pub fn set_render_path(&self, render_path: RenderPath) {
+        self.world.set(render_path);
+    }
+
+    pub fn set_skybox(&self, skybox: Skybox) {
+        self.world.set(skybox);
+    }
+
+    pub fn create_entity(&self, name: &str, pos: Vec3, scale: Vec3, rot_euler_deg: Vec3, parent: Option<Entity>) -> Entity {
         let local_transform_matrix = Mat4::from_scale_rotation_translation(
             scale,
             Quat::from_euler(glam::EulerRot::YXZ, rot_euler_deg.y.to_radians(), rot_euler_deg.x.to_radians(), rot_euler_deg.z.to_radians()),
@@ -101,18 +270,34 @@ impl Ecs {
         }
         entity.id()
     }
-    pub fn add_mesh(&mut self, e: Entity, mesh: Mesh, texture: Option<Texture>) {
+    // Destroys the entity, releasing the GPU buffers of its mesh.
+    pub fn destroy_entity(&self, e: Entity) {
+        let entity = e.entity_view(&self.world);
+        entity.try_get::<&Mesh>(Graphics::delete_mesh);
+        entity.destruct();
+    }
+    pub fn add_mesh(&self, e: Entity, mesh: Mesh, material: Option<Material>) {
 
         e.entity_view(&self.world).set(mesh);
-        if let Some(texture) = texture {
-            e.entity_view(&self.world).set(texture);
+        if let Some(material) = material {
+            e.entity_view(&self.world).set(material);
         }
     }
-    pub fn add_pbr_shader(&mut self, e: Entity, shader: Shader) {
+    pub fn add_terrain(&self, e: Entity, terrain: Terrain) {
+        e.entity_view(&self.world).set(terrain);
+    }
+    pub fn add_terrain_material(&self, e: Entity, material: TerrainMaterial) {
+        e.entity_view(&self.world).set(material);
+    }
+    pub fn add_pbr_shader(&self, e: Entity, shader: Shader) {
         e.entity_view(&self.world).set(PBRShader(shader));
     }
 
-    pub fn add_camera(&mut self, e: Entity, camera: Camera) {
+    pub fn add_light(&self, e: Entity, light: Light) {
+        e.entity_view(&self.world).set(light);
+    }
+
+    pub fn add_camera(&self, e: Entity, camera: Camera) {
        e.entity_view(&self.world).set(camera);
     }
 }
\ No newline at end of file
@@ -0,0 +1,263 @@
+use glam::{vec2, Vec2};
+use crate::terrain::Heightfield;
+
+// Parameters of the erosion pass. Rates are per droplet step (hydraulic) or per sweep (thermal).
+#[derive(Clone, Copy, Debug)]
+pub struct ErosionSettings {
+    // Number of simulated rain droplets, 0 skips hydraulic erosion.
+    pub droplets: u32,
+    pub droplet_lifetime: u32,
+    // How much a droplet keeps its direction instead of following the slope, in [0, 1].
+    pub inertia: f32,
+    pub sediment_capacity: f32,
+    pub min_sediment_capacity: f32,
+    pub erode_rate: f32,
+    pub deposit_rate: f32,
+    pub evaporate_rate: f32,
+    pub gravity: f32,
+    // Number of thermal sweeps over the grid, 0 skips thermal erosion.
+    pub thermal_iterations: u32,
+    // Steepest stable height difference between neighbouring samples.
+    pub talus: f32,
+    pub thermal_rate: f32,
+}
+
+impl Default for ErosionSettings {
+    fn default() -> Self {
+        Self {
+            droplets: 20_000,
+            droplet_lifetime: 30,
+            inertia: 0.05,
+            sediment_capacity: 4.0,
+            min_sediment_capacity: 0.01,
+            erode_rate: 0.3,
+            deposit_rate: 0.3,
+            evaporate_rate: 0.01,
+            gravity: 4.0,
+            thermal_iterations: 10,
+            talus: 0.6,
+            thermal_rate: 0.5,
+        }
+    }
+}
+
+// Runs droplet-based hydraulic erosion followed by thermal slumping.
+// The result only depends on the heightfield, the settings and `seed`.
+pub fn erode(heightfield: &mut Heightfield, settings: &ErosionSettings, seed: u64) {
+    let mut rng = Rng::new(seed);
+    for _ in 0..settings.droplets {
+        simulate_droplet(heightfield, settings, &mut rng);
+    }
+    for _ in 0..settings.thermal_iterations {
+        thermal_sweep(heightfield, settings);
+    }
+}
+
+fn simulate_droplet(heightfield: &mut Heightfield, settings: &ErosionSettings, rng: &mut Rng) {
+    let max_x = (heightfield.width - 1) as f32;
+    let max_z = (heightfield.depth - 1) as f32;
+    let mut pos = vec2(rng.next_f32() * max_x, rng.next_f32() * max_z);
+    let mut dir = Vec2::ZERO;
+    let mut speed = 1.0;
+    let mut water = 1.0;
+    let mut sediment = 0.0;
+
+    for _ in 0..settings.droplet_lifetime {
+        let (height, gradient) = height_and_gradient(heightfield, pos);
+
+        dir = dir * settings.inertia - gradient * (1.0 - settings.inertia);
+        if dir.length_squared() < 1e-12 {
+            // Flat ground: pick a random direction rather than getting stuck.
+            let angle = rng.next_f32() * std::f32::consts::TAU;
+            dir = vec2(angle.cos(), angle.sin());
+        }
+        dir = dir.normalize();
+
+        let old_pos = pos;
+        pos += dir;
+        if pos.x < 0.0 || pos.y < 0.0 || pos.x >= max_x || pos.y >= max_z {
+            break;
+        }
+
+        let delta_height = height_and_gradient(heightfield, pos).0 - height;
+        let capacity = (-delta_height * speed * water * settings.sediment_capacity).max(settings.min_sediment_capacity);
+
+        if sediment > capacity || delta_height > 0.0 {
+            // Going uphill fills the pit behind the droplet, otherwise drop the excess.
+            let deposit = if delta_height > 0.0 {
+                delta_height.min(sediment)
+            } else {
+                (sediment - capacity) * settings.deposit_rate
+            };
+            sediment -= deposit;
+            splat(heightfield, old_pos, deposit);
+        } else {
+            let eroded = ((capacity - sediment) * settings.erode_rate).min(-delta_height);
+            splat(heightfield, old_pos, -eroded);
+            sediment += eroded;
+        }
+
+        speed = (speed * speed - delta_height * settings.gravity).max(0.0).sqrt();
+        water *= 1.0 - settings.evaporate_rate;
+    }
+}
+
+// Bilinear height and its gradient at a position inside the grid.
+fn height_and_gradient(heightfield: &Heightfield, pos: Vec2) -> (f32, Vec2) {
+    let x = pos.x as u32;
+    let z = pos.y as u32;
+    let fx = pos.x - x as f32;
+    let fz = pos.y - z as f32;
+
+    let h00 = heightfield.height(x, z);
+    let h10 = heightfield.height(x + 1, z);
+    let h01 = heightfield.height(x, z + 1);
+    let h11 = heightfield.height(x + 1, z + 1);
+
+    let gradient = vec2(
+        (h10 - h00) * (1.0 - fz) + (h11 - h01) * fz,
+        (h01 - h00) * (1.0 - fx) + (h11 - h10) * fx,
+    );
+    let height = h00 * (1.0 - fx) * (1.0 - fz) + h10 * fx * (1.0 - fz) + h01 * (1.0 - fx) * fz + h11 * fx * fz;
+    (height, gradient)
+}
+
+// Adds `amount` to the four samples around `pos`, weighted bilinearly.
+fn splat(heightfield: &mut Heightfield, pos: Vec2, amount: f32) {
+    let x = pos.x as u32;
+    let z = pos.y as u32;
+    let fx = pos.x - x as f32;
+    let fz = pos.y - z as f32;
+    let width = heightfield.width;
+    let heights = &mut heightfield.heights;
+
+    heights[(z * width + x) as usize] += amount * (1.0 - fx) * (1.0 - fz);
+    heights[(z * width + x + 1) as usize] += amount * fx * (1.0 - fz);
+    heights[((z + 1) * width + x) as usize] += amount * (1.0 - fx) * fz;
+    heights[((z + 1) * width + x + 1) as usize] += amount * fx * fz;
+}
+
+// Moves material from each sample to lower neighbours wherever the slope exceeds the talus.
+fn thermal_sweep(heightfield: &mut Heightfield, settings: &ErosionSettings) {
+    let width = heightfield.width as usize;
+    let depth = heightfield.depth as usize;
+    let heights = &mut heightfield.heights;
+
+    for z in 0..depth {
+        for x in 0..width {
+            let index = z * width + x;
+            let neighbours = [
+                (x + 1 < width).then(|| index + 1),
+                (z + 1 < depth).then(|| index + width),
+            ];
+            for neighbour in neighbours.into_iter().flatten() {
+                let diff = heights[index] - heights[neighbour];
+                if diff.abs() > settings.talus {
+                    let moved = settings.thermal_rate * (diff.abs() - settings.talus) / 2.0 * diff.signum();
+                    heights[index] -= moved;
+                    heights[neighbour] += moved;
+                }
+            }
+        }
+    }
+}
+
+// SplitMix64, small and stable across platforms so a seed always erodes the same way.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1).
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bumpy slope, steep enough in places for both passes to move material.
+    fn hills() -> Heightfield {
+        let size = 33;
+        let heights = (0..size * size)
+            .map(|i| {
+                let (x, z) = ((i % size) as f32, (i / size) as f32);
+                x * 0.3 + (x * 0.4).sin() * 3.0 + (z * 0.3).cos() * 2.0
+            })
+            .collect();
+        Heightfield { width: size, depth: size, heights }
+    }
+
+    fn settings() -> ErosionSettings {
+        ErosionSettings { droplets: 2_000, ..ErosionSettings::default() }
+    }
+
+    fn eroded(settings: &ErosionSettings, seed: u64) -> Heightfield {
+        let mut heightfield = hills();
+        erode(&mut heightfield, settings, seed);
+        heightfield
+    }
+
+    fn bits(heightfield: &Heightfield) -> Vec<u32> {
+        heightfield.heights.iter().map(|h| h.to_bits()).collect()
+    }
+
+    fn bounds(heightfield: &Heightfield) -> (f32, f32) {
+        heightfield.heights.iter().fold((f32::MAX, f32::MIN), |(min, max), &h| (min.min(h), max.max(h)))
+    }
+
+    fn mass(heightfield: &Heightfield) -> f64 {
+        heightfield.heights.iter().map(|&h| h as f64).sum()
+    }
+
+    #[test]
+    fn same_seed_erodes_identically() {
+        assert_eq!(bits(&eroded(&settings(), 7)), bits(&eroded(&settings(), 7)));
+    }
+
+    #[test]
+    fn different_seed_erodes_differently() {
+        assert_ne!(bits(&eroded(&settings(), 7)), bits(&eroded(&settings(), 8)));
+    }
+
+    #[test]
+    fn erosion_changes_heights_within_bounds() {
+        let original = hills();
+        let result = eroded(&settings(), 7);
+        assert_ne!(bits(&result), bits(&original));
+
+        let (min, max) = bounds(&original);
+        let (eroded_min, eroded_max) = bounds(&result);
+        assert!(eroded_min >= min - 1e-3 && eroded_max <= max + 1e-3, "{:?} outside {:?}", (eroded_min, eroded_max), (min, max));
+        // Droplets only deposit what they picked up, some of it leaves the grid with them.
+        assert!(mass(&result) <= mass(&original) + 1e-2);
+    }
+
+    #[test]
+    fn thermal_erosion_keeps_mass_and_flattens_steep_slopes() {
+        let settings = ErosionSettings { droplets: 0, thermal_iterations: 50, ..ErosionSettings::default() };
+        let original = hills();
+        let result = eroded(&settings, 7);
+        assert!((mass(&result) - mass(&original)).abs() < 1e-2);
+
+        let steepest = |heightfield: &Heightfield| {
+            (0..heightfield.depth)
+                .flat_map(|z| (0..heightfield.width - 1).map(move |x| (x, z)))
+                .map(|(x, z)| (heightfield.height(x + 1, z) - heightfield.height(x, z)).abs())
+                .fold(0.0, f32::max)
+        };
+        assert!(steepest(&result) < steepest(&original));
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::path::Path;
+use flecs_ecs::prelude::Entity;
+use glam::{vec2, EulerRot, Quat, Vec3, Vec4};
+use gltf::image::Format;
+use crate::components::{AlphaMode, Material, Texture, Vertex};
+use crate::ecs::Ecs;
+use crate::graphics::Graphics;
+use crate::shader::Shader;
+
+// Loads a .gltf/.glb file and spawns the node hierarchy of its default scene.
+// Every node becomes an entity parented to its glTF parent (or to `parent` for root nodes),
+// every primitive becomes a `Mesh` and its glTF material becomes a `Material`.
+// Returns the root entities of the scene.
+pub fn load_gltf(world: &Ecs, path: &str, shader: Shader, parent: Option<Entity>) -> Result<Vec<Entity>, String> {
+    let (document, buffers, images) = gltf::import(path).map_err(|e| format!("Failed to load glTF {}: {}", path, e))?;
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| format!("glTF {} does not contain a scene", path))?;
+
+    let prefix = Path::new(path)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "gltf".to_string());
+
+    let mut loader = GltfLoader {
+        prefix,
+        shader,
+        buffers,
+        images,
+        textures: HashMap::new(),
+    };
+
+    let mut roots = Vec::new();
+    for node in scene.nodes() {
+        roots.push(loader.spawn_node(world, &node, parent)?);
+    }
+    Ok(roots)
+}
+
+struct GltfLoader {
+    prefix: String,
+    shader: Shader,
+    buffers: Vec<gltf::buffer::Data>,
+    images: Vec<gltf::image::Data>,
+    // Images shared by several materials are only uploaded once.
+    textures: HashMap<usize, Texture>,
+}
+
+impl GltfLoader {
+    fn spawn_node(&mut self, world: &Ecs, node: &gltf::Node, parent: Option<Entity>) -> Result<Entity, String> {
+        let (translation, rotation, scale) = node.transform().decomposed();
+        let (yaw, pitch, roll) = Quat::from_array(rotation).to_euler(EulerRot::YXZ);
+        let rot_euler_deg = Vec3::new(pitch.to_degrees(), yaw.to_degrees(), roll.to_degrees());
+
+        // Node names are optional and not unique, the index keeps entity names apart.
+        let name = format!("{}_{}_{}", self.prefix, node.name().unwrap_or("node"), node.index());
+        let entity = world.create_entity(&name, Vec3::from(translation), Vec3::from(scale), rot_euler_deg, parent);
+
+        if let Some(mesh) = node.mesh() {
+            for (i, primitive) in mesh.primitives().enumerate() {
+                // The first primitive lives on the node itself, the rest on child entities.
+                let target = if i == 0 {
+                    entity
+                } else {
+                    let child_name = format!("{}_primitive{}", name, i);
+                    world.create_entity(&child_name, Vec3::ZERO, Vec3::ONE, Vec3::ZERO, Some(entity))
+                };
+                self.add_primitive(world, target, &primitive)?;
+            }
+        }
+
+        for child in node.children() {
+            self.spawn_node(world, &child, Some(entity))?;
+        }
+        Ok(entity)
+    }
+
+    fn add_primitive(&mut self, world: &Ecs, entity: Entity, primitive: &gltf::Primitive) -> Result<(), String> {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            return Err(format!("Unsupported glTF primitive mode {:?}", primitive.mode()));
+        }
+
+        let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+        let positions = reader
+            .read_positions()
+            .ok_or_else(|| "glTF primitive has no POSITION attribute".to_string())?;
+
+        let mut vertices: Vec<Vertex> = positions
+            .map(|p| Vertex {
+                position: Vec3::from(p),
+                normal: Vec3::Y,
+                uv: vec2(0.0, 0.0),
+            })
+            .collect();
+        if let Some(normals) = reader.read_normals() {
+            for (vertex, normal) in vertices.iter_mut().zip(normals) {
+                vertex.normal = Vec3::from(normal);
+            }
+        }
+        if let Some(uvs) = reader.read_tex_coords(0) {
+            for (vertex, uv) in vertices.iter_mut().zip(uvs.into_f32()) {
+                vertex.uv = vec2(uv[0], uv[1]);
+            }
+        }
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..vertices.len() as u32).collect(),
+        };
+
+        let material = self.material(&primitive.material());
+        world.add_pbr_shader(entity, self.shader.clone());
+        world.add_mesh(entity, Graphics::create_mesh(vertices, indices), Some(material));
+        Ok(())
+    }
+
+    fn material(&mut self, material: &gltf::Material) -> Material {
+        let pbr = material.pbr_metallic_roughness();
+        let alpha_mode = match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask(material.alpha_cutoff().unwrap_or(0.5)),
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        };
+        let normal = material.normal_texture();
+        let occlusion = material.occlusion_texture();
+
+        Material {
+            base_color: Vec4::from(pbr.base_color_factor()),
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            emissive: Vec3::from(material.emissive_factor()),
+            normal_scale: normal.as_ref().map_or(1.0, |n| n.scale()),
+            occlusion_strength: occlusion.as_ref().map_or(1.0, |o| o.strength()),
+            alpha_mode,
+            base_color_texture: pbr.base_color_texture().map(|info| self.texture(info.texture().source().index())),
+            metallic_roughness_texture: pbr.metallic_roughness_texture().map(|info| self.texture(info.texture().source().index())),
+            normal_texture: normal.map(|n| self.texture(n.texture().source().index())),
+            emissive_texture: material.emissive_texture().map(|info| self.texture(info.texture().source().index())),
+            occlusion_texture: occlusion.map(|o| self.texture(o.texture().source().index())),
+        }
+    }
+
+    fn texture(&mut self, image_index: usize) -> Texture {
+        if let Some(texture) = self.textures.get(&image_index) {
+            return *texture;
+        }
+
+        let image = &self.images[image_index];
+        let (format, data_type) = match image.format {
+            Format::R8 => (gl::RED, gl::UNSIGNED_BYTE),
+            Format::R8G8 => (gl::RG, gl::UNSIGNED_BYTE),
+            Format::R8G8B8 => (gl::RGB, gl::UNSIGNED_BYTE),
+            Format::R8G8B8A8 => (gl::RGBA, gl::UNSIGNED_BYTE),
+            Format::B8G8R8 => (gl::BGR, gl::UNSIGNED_BYTE),
+            Format::B8G8R8A8 => (gl::BGRA, gl::UNSIGNED_BYTE),
+            Format::R16 => (gl::RED, gl::UNSIGNED_SHORT),
+            Format::R16G16 => (gl::RG, gl::UNSIGNED_SHORT),
+            Format::R16G16B16 => (gl::RGB, gl::UNSIGNED_SHORT),
+            Format::R16G16B16A16 => (gl::RGBA, gl::UNSIGNED_SHORT),
+        };
+        let texture = Graphics::create_texture(image.width, image.height, format, data_type, &image.pixels);
+        self.textures.insert(image_index, texture);
+        texture
+    }
+}
@@ -0,0 +1,327 @@
+// Golden-image regression tests. Canned scenes are rendered headless at fixed camera poses and
+// compared against the references in tests/golden with a perceptual (CIE Lab) tolerance.
+//
+// - `UPDATE_GOLDEN=1 cargo test golden` records new references.
+// - On a mismatch the rendered frame and a diff image are written to target/golden.
+// - The rendering tests need a GL context, they are ignored unless the `golden` feature is on.
+//   Software Mesa works: `LIBGL_ALWAYS_SOFTWARE=1 cargo test --features golden golden`.
+
+use std::sync::Mutex;
+use flecs_ecs::prelude::Entity;
+use glam::{Mat4, Vec3};
+use image::{Rgba, RgbaImage};
+use crate::components::*;
+use crate::ecs::Ecs;
+use crate::graphics::Graphics;
+use crate::shader::{load_shader, load_shader_with};
+use crate::shader_preprocessor::Defines;
+use crate::post::PostProcess;
+use crate::shadows::ShadowRenderer;
+use crate::terrain::{TerrainGenerator, TerrainLod, TerrainSettings, TerrainStreamer};
+use crate::{CUBE_INDICES, CUBE_VERTICES};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+// Enough frames for the terrain around the camera to stream in.
+const FRAMES: u32 = 8;
+const REFERENCE_DIR: &str = "tests/golden";
+const OUTPUT_DIR: &str = "target/golden";
+
+// SDL and the GL context are process wide, one test at a time uses them. Also taken by the GL
+// tests in shader.rs.
+pub(crate) static GL_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Clone, Copy, Debug)]
+struct Tolerance {
+    // Largest CIE76 color difference of a matching pixel, about 2.3 is just noticeable.
+    max_delta_e: f32,
+    // Fraction of pixels allowed to differ more, absorbs rasterization differences on edges.
+    max_failing_fraction: f32,
+}
+
+const TOLERANCE: Tolerance = Tolerance { max_delta_e: 3.0, max_failing_fraction: 0.002 };
+
+struct Comparison {
+    failing_pixels: u32,
+    max_delta_e: f32,
+    diff: RgbaImage,
+}
+
+// Per pixel Lab distance. The diff image is the reference in grey with failing pixels in red.
+fn compare(actual: &RgbaImage, reference: &RgbaImage, tolerance: Tolerance) -> Comparison {
+    let mut diff = RgbaImage::new(reference.width(), reference.height());
+    let mut failing_pixels = 0;
+    let mut max_delta_e: f32 = 0.0;
+    for (x, y, expected) in reference.enumerate_pixels() {
+        let delta_e = (srgb_to_lab(*actual.get_pixel(x, y)) - srgb_to_lab(*expected)).length();
+        max_delta_e = max_delta_e.max(delta_e);
+        let pixel = if delta_e > tolerance.max_delta_e {
+            failing_pixels += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let grey = (srgb_to_lab(*expected).x * 2.55 * 0.3) as u8;
+            Rgba([grey, grey, grey, 255])
+        };
+        diff.put_pixel(x, y, pixel);
+    }
+    Comparison { failing_pixels, max_delta_e, diff }
+}
+
+// sRGB (D65) to CIE L*a*b*, as (L, a, b).
+fn srgb_to_lab(pixel: Rgba<u8>) -> Vec3 {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(pixel[0]), linear(pixel[1]), linear(pixel[2]));
+    let xyz = Vec3::new(
+        (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047,
+        0.2126 * r + 0.7152 * g + 0.0722 * b,
+        (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883,
+    );
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(xyz.x), f(xyz.y), f(xyz.z));
+    Vec3::new(116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+// Checks a frame against tests/golden/<name>.png, or records it when UPDATE_GOLDEN is set.
+fn assert_golden(name: &str, actual: &RgbaImage) {
+    let reference_path = format!("{}/{}.png", REFERENCE_DIR, name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(REFERENCE_DIR).unwrap();
+        actual.save(&reference_path).unwrap();
+        return;
+    }
+
+    std::fs::create_dir_all(OUTPUT_DIR).unwrap();
+    let actual_path = format!("{}/{}.actual.png", OUTPUT_DIR, name);
+    let reference = match image::open(&reference_path) {
+        Ok(reference) => reference.into_rgba8(),
+        Err(e) => {
+            actual.save(&actual_path).unwrap();
+            panic!("No reference for {} ({}), rendered frame saved to {}. Run with UPDATE_GOLDEN=1 to record it.", name, e, actual_path);
+        }
+    };
+    assert_eq!(reference.dimensions(), actual.dimensions(), "{} was rendered at a different size than its reference", name);
+
+    let comparison = compare(actual, &reference, TOLERANCE);
+    let allowed = (TOLERANCE.max_failing_fraction * (WIDTH * HEIGHT) as f32) as u32;
+    if comparison.failing_pixels > allowed {
+        let diff_path = format!("{}/{}.diff.png", OUTPUT_DIR, name);
+        actual.save(&actual_path).unwrap();
+        comparison.diff.save(&diff_path).unwrap();
+        panic!(
+            "{} differs from its reference in {} pixels (max delta E {:.1}, {} allowed), see {} and {}",
+            name, comparison.failing_pixels, comparison.max_delta_e, allowed, actual_path, diff_path
+        );
+    }
+}
+
+// Renders FRAMES frames of the scene built by `setup` through the regular frame pipeline
+// and reads back the last one.
+fn render_scene(eye: Vec3, target: Vec3, setup: impl FnOnce(&Ecs) -> Result<Option<TerrainStreamer>, String>) -> RgbaImage {
+    let _lock = GL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let graphics = Graphics::new_headless(WIDTH, HEIGHT).unwrap_or_else(|e| panic!("Failed to create a headless GL context: {}", e));
+
+    let world = Ecs::new();
+    let camera = world.create_entity("camera", eye, Vec3::ONE, Vec3::ZERO, None);
+    camera.entity_view(&world.world).set_pair::<Transform, Local>(Transform(Mat4::look_at_rh(eye, target, Vec3::Y).inverse()));
+    world.add_camera(camera, Camera {
+        projection: Mat4::perspective_rh_gl(45.0f32.to_radians(), WIDTH as f32 / HEIGHT as f32, 0.1, 100.0),
+    });
+    let mut terrain = setup(&world).unwrap();
+
+    let (update_system, render_system, camera_system, light_system) = world.create_system();
+    let emissive_system = world.create_emissive_system();
+    let shadows = ShadowRenderer::new(&world, 1024, 2).unwrap();
+    let post = PostProcess::new(WIDTH, HEIGHT).unwrap();
+
+    for _ in 0..FRAMES {
+        if let Some(terrain) = &mut terrain {
+            terrain.update(&world, eye);
+        }
+        update_system.run();
+        camera_system.run();
+        light_system.run();
+        shadows.render(&world);
+        post.begin();
+        graphics.begin_frame();
+        render_system.run();
+        emissive_system.run();
+        post.end(graphics.framebuffer());
+    }
+    graphics.read_frame()
+}
+
+// 1x1 texture of a single color, the canned scenes do not depend on asset files.
+fn solid_texture(color: [u8; 3]) -> Texture {
+    Graphics::create_texture(1, 1, gl::RGB, gl::UNSIGNED_BYTE, &color)
+}
+
+fn add_sun(world: &Ecs) -> Entity {
+    let sun = world.create_entity("sun", Vec3::ZERO, Vec3::ONE, Vec3::new(-50.0, 30.0, 0.0), None);
+    world.add_light(sun, Light {
+        color: Vec3::new(1.0, 0.95, 0.85),
+        intensity: 3.0,
+        kind: LightKind::Directional,
+        shadow: Some(ShadowSettings::default()),
+    });
+    sun
+}
+
+fn add_box(world: &Ecs, name: &str, position: Vec3, scale: Vec3, material: Material) -> Result<Entity, String> {
+    let entity = world.create_entity(name, position, scale, Vec3::ZERO, None);
+    world.add_pbr_shader(entity, load_shader_with("assets/pbr.vert", "assets/pbr.frag", &Defines::new().flag("SHADOWS"))?);
+    world.add_mesh(entity, Graphics::create_mesh(CUBE_VERTICES.to_vec(), CUBE_INDICES.to_vec()), Some(material));
+    Ok(entity)
+}
+
+#[test]
+#[cfg_attr(not(feature = "golden"), ignore = "needs a GL context, run with --features golden")]
+fn golden_cube() {
+    let frame = render_scene(Vec3::new(-1.5, 1.2, 2.5), Vec3::ZERO, |world| {
+        add_sun(world);
+        add_box(world, "cube", Vec3::ZERO, Vec3::ONE, Material {
+            base_color_texture: Some(solid_texture([200, 80, 60])),
+            roughness: 0.3,
+            ..Material::default()
+        })?;
+        Ok(None)
+    });
+    assert_golden("cube", &frame);
+}
+
+#[test]
+#[cfg_attr(not(feature = "golden"), ignore = "needs a GL context, run with --features golden")]
+fn golden_terrain() {
+    let frame = render_scene(Vec3::new(0.0, 25.0, 0.0), Vec3::new(40.0, 5.0, 40.0), |world| {
+        add_sun(world);
+        let layer = |color, height: Vec3, slope: Vec3| TerrainLayer { texture: solid_texture(color), tiling: 0.2, height, slope };
+        let material = TerrainMaterial {
+            layers: vec![
+                layer([194, 178, 128], Vec3::new(-100.0, 2.0, 0.5), Vec3::new(0.0, 0.3, 0.1)),
+                layer([80, 140, 60], Vec3::new(2.0, 7.0, 0.5), Vec3::new(0.0, 0.3, 0.1)),
+                layer([110, 100, 95], Vec3::new(-100.0, 100.0, 0.0), Vec3::new(0.3, 1.0, 0.1)),
+                layer([240, 240, 245], Vec3::new(7.0, 100.0, 0.5), Vec3::new(0.0, 0.3, 0.1)),
+            ],
+            splat_map: None,
+        };
+        let shader = load_shader_with("assets/terrain.vert", "assets/terrain.frag", &Defines::new().flag("SHADOWS"))?;
+        let generator = TerrainGenerator::new(TerrainSettings::default());
+        Ok(Some(TerrainStreamer::new(generator, 64, 1, TerrainLod::new(2.0, HEIGHT, 45.0), shader, material)))
+    });
+    assert_golden("terrain", &frame);
+}
+
+#[test]
+#[cfg_attr(not(feature = "golden"), ignore = "needs a GL context, run with --features golden")]
+fn golden_lights() {
+    let frame = render_scene(Vec3::new(0.0, 4.0, 7.0), Vec3::ZERO, |world| {
+        add_box(world, "floor", Vec3::new(0.0, -0.55, 0.0), Vec3::new(10.0, 0.1, 10.0), Material {
+            base_color: glam::Vec4::new(0.8, 0.8, 0.8, 1.0),
+            roughness: 0.8,
+            ..Material::default()
+        })?;
+        add_box(world, "pillar", Vec3::new(0.0, 0.5, 0.0), Vec3::new(0.5, 2.0, 0.5), Material {
+            metallic: 1.0,
+            roughness: 0.4,
+            ..Material::default()
+        })?;
+
+        let lamp = world.create_entity("lamp", Vec3::new(2.0, 1.5, 1.0), Vec3::ONE, Vec3::ZERO, None);
+        world.add_light(lamp, Light {
+            color: Vec3::new(1.0, 0.6, 0.3),
+            intensity: 20.0,
+            kind: LightKind::Point { range: 10.0 },
+            shadow: None,
+        });
+        let spot = world.create_entity("spot", Vec3::new(-2.0, 4.0, 2.0), Vec3::ONE, Vec3::new(-60.0, -45.0, 0.0), None);
+        world.add_light(spot, Light {
+            color: Vec3::new(0.6, 0.8, 1.0),
+            intensity: 40.0,
+            kind: LightKind::Spot { range: 12.0, inner_angle: 20.0, outer_angle: 30.0 },
+            shadow: Some(ShadowSettings::default()),
+        });
+
+        let orb = world.create_entity("orb", Vec3::new(-1.5, 1.0, 1.5), Vec3::splat(0.3), Vec3::ZERO, None);
+        world.add_mesh(orb, Graphics::create_sphere_mesh(32, 16), None);
+        world.add_emission(orb, Emission {
+            orb_color: Vec3::new(0.3, 0.7, 1.0),
+            intensity: 4.0,
+            center_position: Vec3::ZERO,
+            radius: 0.3,
+        }, load_shader("assets/pbr.vert", "assets/emissive.frag")?);
+        Ok(None)
+    });
+    assert_golden("lights", &frame);
+}
+
+#[test]
+#[cfg_attr(not(feature = "golden"), ignore = "needs a GL context, run with --features golden")]
+fn golden_blended() {
+    let frame = render_scene(Vec3::new(0.0, 1.0, 5.0), Vec3::new(0.0, 0.5, 0.0), |world| {
+        add_sun(world);
+        let shader = load_shader_with("assets/pbr.vert", "assets/pbr.frag", &Defines::new().flag("SHADOWS"))?;
+        // One mesh for every box, the opaque ones are drawn instanced and the panes from the same VAO.
+        let mesh = Graphics::create_mesh(CUBE_VERTICES.to_vec(), CUBE_INDICES.to_vec());
+        let add = |name: &str, position: Vec3, scale: Vec3, material: Material| {
+            let entity = world.create_entity(name, position, scale, Vec3::ZERO, None);
+            world.add_pbr_shader(entity, shader.clone());
+            world.add_mesh(entity, mesh.clone(), Some(material));
+        };
+        for (i, x) in [-1.5, 0.0, 1.5].into_iter().enumerate() {
+            add(&format!("box_{}", i), Vec3::new(x, 0.0, -1.0), Vec3::splat(0.8), Material {
+                base_color: glam::Vec4::new(0.8, 0.8, 0.8, 1.0),
+                ..Material::default()
+            });
+        }
+        // Added nearest first, drawn back to front.
+        let pane = |color: Vec3| Material {
+            base_color: color.extend(0.5),
+            alpha_mode: AlphaMode::Blend,
+            ..Material::default()
+        };
+        add("near_pane", Vec3::new(0.3, 0.6, 1.5), Vec3::new(1.2, 1.2, 0.05), pane(Vec3::new(1.0, 0.1, 0.1)));
+        add("far_pane", Vec3::new(-0.3, 0.4, 0.5), Vec3::new(1.2, 1.2, 0.05), pane(Vec3::new(0.1, 0.2, 1.0)));
+        Ok(None)
+    });
+    assert_golden("blended", &frame);
+}
+
+#[test]
+fn compare_accepts_identical_images() {
+    let image = RgbaImage::from_fn(16, 16, |x, y| Rgba([(x * 16) as u8, (y * 16) as u8, 128, 255]));
+    let comparison = compare(&image, &image, TOLERANCE);
+    assert_eq!(comparison.failing_pixels, 0);
+    assert_eq!(comparison.max_delta_e, 0.0);
+}
+
+#[test]
+fn compare_tolerates_imperceptible_noise() {
+    let reference = RgbaImage::from_pixel(16, 16, Rgba([120, 130, 140, 255]));
+    let actual = RgbaImage::from_fn(16, 16, |x, y| {
+        let noise = ((x + y) % 2) as u8;
+        Rgba([120 + noise, 130 - noise, 140, 255])
+    });
+    assert_eq!(compare(&actual, &reference, TOLERANCE).failing_pixels, 0);
+}
+
+#[test]
+fn compare_flags_and_marks_changed_pixels() {
+    let reference = RgbaImage::from_pixel(16, 16, Rgba([120, 130, 140, 255]));
+    let mut actual = reference.clone();
+    actual.put_pixel(3, 4, Rgba([255, 255, 0, 255]));
+    let comparison = compare(&actual, &reference, TOLERANCE);
+    assert_eq!(comparison.failing_pixels, 1);
+    assert!(comparison.max_delta_e > TOLERANCE.max_delta_e);
+    assert_eq!(*comparison.diff.get_pixel(3, 4), Rgba([255, 0, 0, 255]));
+    assert_ne!(*comparison.diff.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+}
+
+#[test]
+fn lab_conversion_matches_known_values() {
+    let white = srgb_to_lab(Rgba([255, 255, 255, 255]));
+    assert!((white.x - 100.0).abs() < 0.1 && white.y.abs() < 0.1 && white.z.abs() < 0.1);
+    let black = srgb_to_lab(Rgba([0, 0, 0, 255]));
+    assert!(black.length() < 0.1);
+}
@@ -1,70 +1,84 @@
-use super::components::{Mesh, Texture, Vertex};
+use super::components::{Mesh, Skybox, Texture, Vertex};
 use sdl2::video::{GLProfile, Window};
 use sdl2::{Sdl, VideoSubsystem};
-use std::ffi::{c_void, CString};
-use std::fs;
+use std::ffi::c_void;
 use std::ptr;
 use flecs_ecs::macros::Component;
 use gl::types::GLsizei;
 use glam::{vec2, vec3, Vec3};
-use noise::{Fbm, NoiseFn, Perlin};
-
-#[derive(Clone, Copy, Debug)]
-pub struct Shader {
-    pub id: u32
-}
-
-impl Shader {
-    pub  fn set_uniform_mat4(&self, name: &str, mat: &glam::Mat4) {
-        unsafe {
-            let c_name = CString::new(name).unwrap();
-            let location = gl::GetUniformLocation(self.id, c_name.as_ptr());
-            gl::UniformMatrix4fv(location, 1, gl::FALSE, mat.to_cols_array().as_ptr());
-        }
-    }
-
-    pub fn set_uniform_vec3(&self, name: &str, vec: &glam::Vec3) {
-        unsafe {
-            let c_name = CString::new(name).unwrap();
-            let location = gl::GetUniformLocation(self.id, c_name.as_ptr());
-            gl::Uniform3fv(location, 1, vec.to_array().as_ptr());
-        }
-    }
-
-    pub  fn use_program(&self) {
-        unsafe {
-            gl::UseProgram(self.id);
-        }
-    }
-}
+use crate::culling::Aabb;
+use crate::render_state;
+use crate::shader;
+
+// Uniform buffer binding points shared by every program, see `load_shader`.
+pub const LIGHTS_BINDING: u32 = 0;
+pub const SHADOWS_BINDING: u32 = 1;
+pub const CAMERA_BINDING: u32 = 2;
+
+// Texture units reserved for per-frame maps, above the ones materials use.
+pub const IRRADIANCE_UNIT: u32 = 5;
+pub const PREFILTER_UNIT: u32 = 6;
+pub const BRDF_LUT_UNIT: u32 = 7;
+pub const CASCADE_SHADOW_UNIT: u32 = 8;
+pub const SPOT_SHADOW_UNIT: u32 = 9;
 
 pub struct Graphics {
     pub sdl_context: Sdl,
     pub window: Window,
     _gl_context: sdl2::video::GLContext,
+    // Where finished frames go: 0 for the window, an offscreen FBO when headless.
+    framebuffer: u32,
+    headless: bool,
 }
 
 impl Graphics {
     pub fn new(title: &str, width: u32, height: u32) -> Result<Self, String> {
+        Graphics::create(title, width, height, false)
+    }
+
+    // Renders without showing anything: the window stays hidden and frames go to an offscreen
+    // FBO that `read_frame`/`capture_frame` read back. Without a display SDL's offscreen (EGL)
+    // video driver is used, set LIBGL_ALWAYS_SOFTWARE=1 to force Mesa's software rasterizer.
+    pub fn new_headless(width: u32, height: u32) -> Result<Self, String> {
+        let has_display = std::env::var_os("DISPLAY").is_some() || std::env::var_os("WAYLAND_DISPLAY").is_some();
+        if std::env::var_os("SDL_VIDEODRIVER").is_none() && !has_display {
+            sdl2::hint::set("SDL_VIDEODRIVER", "offscreen");
+        }
+        let mut graphics = Graphics::create("headless", width, height, true)?;
+        let color = Graphics::create_render_texture(width, height, gl::RGBA8);
+        graphics.framebuffer = Graphics::create_framebuffer(&[color], Some(Graphics::create_depth_texture(width, height)))?;
+        Ok(graphics)
+    }
+
+    fn create(title: &str, width: u32, height: u32, headless: bool) -> Result<Self, String> {
         let sdl_context = sdl2::init()?;
         let video_subsystem = sdl_context.video()?;
 
         let gl_attr = video_subsystem.gl_attr();
         gl_attr.set_context_profile(GLProfile::Core);
-        gl_attr.set_context_version(4, 1);
         gl_attr.set_double_buffer(true);
         gl_attr.set_depth_size(24);
         gl_attr.set_context_flags().debug().set();
 
-        let window = video_subsystem
-            .window(title, width, height)
-            .position_centered()
-            .opengl()
-            .build()
-            .map_err(|e| e.to_string())?;
-
-        let _gl_context = window.gl_create_context()?;
+        let mut window_builder = video_subsystem.window(title, width, height);
+        window_builder.position_centered().opengl();
+        if headless {
+            window_builder.hidden();
+        }
+        let window = window_builder.build().map_err(|e| e.to_string())?;
+
+        // 4.3 adds compute shaders and storage buffers, macOS stops at 4.1.
+        gl_attr.set_context_version(4, 3);
+        let _gl_context = match window.gl_create_context() {
+            Ok(context) => context,
+            Err(_) => {
+                gl_attr.set_context_version(4, 1);
+                window.gl_create_context()?
+            }
+        };
         gl::load_with(|s| video_subsystem.gl_get_proc_address(s) as *const _);
+        render_state::reset();
+        shader::clear_shader_cache();
 
         // // Setup OpenGL debug callback
         // unsafe {
@@ -78,9 +92,19 @@ impl Graphics {
             sdl_context,
             window,
             _gl_context,
+            framebuffer: 0,
+            headless,
         })
     }
 
+    pub fn framebuffer(&self) -> u32 {
+        self.framebuffer
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.window.size()
+    }
+
     pub fn begin_frame(&self) {
         unsafe {
             gl::Viewport(0, 0, self.window.size().0 as i32, self.window.size().1 as i32);
@@ -92,104 +116,33 @@ impl Graphics {
     }
 
     pub fn end_frame(&self) {
-        self.window.gl_swap_window();
-    }
-    fn perlin_noise(x: f32, y: f32, octaves: i32, lacunarity: f32, persistence: f32) -> f32 {
-        // Create a new Fractal Brownian Motion instance with a Perlin noise source.
-        let mut fbm = Fbm::<Perlin>::new(0); // Using a seed of 0 for reproducibility
-        fbm.octaves = octaves as usize;
-        fbm.lacunarity = lacunarity as f64;
-        fbm.persistence = persistence as f64;
-
-        // The noise function expects a 2D point as an array of f64.
-        let point = [x as f64, y as f64];
-
-        // Get the noise value, which is in the range of roughly [-1.0, 1.0].
-
-        let noise_value = fbm.get(point) as f32;
-
-        // Normalize the value to the [0.0, 1.0] range, which is ideal for heightmaps.
-        (noise_value + 1.0) / 2.0
-    }
-    pub fn create_terrain(terrain_width: u32, terrain_height: u32) -> Mesh {
-        let num_vertices = (terrain_width * terrain_height) as usize;
-        let mut vertices = vec![Vertex::default(); num_vertices];
-
-        // Pre-allocate vector capacity for performance.
-        let num_indices = ((terrain_width - 1) * (terrain_height - 1) * 6) as usize;
-        let mut indices = Vec::with_capacity(num_indices);
-
-        let perlin_scale = 0.02;     // Zoom out to create large features
-        let mountain_height = 10.0; // A multiplier to make mountains taller
-
-        // --- 1. Generate Vertices and Height ---
-        for y in 0..terrain_height {
-            for x in 0..terrain_width {
-                let index = (y * terrain_width + x) as usize;
-
-                // Generate height using the Perlin noise function.
-                let height = Graphics::perlin_noise(
-                    x as f32 * perlin_scale,
-                    y as f32 * perlin_scale,
-                    6,      // octaves
-                    2.0,    // lacunarity
-                    0.5,    // persistence
-                );
-
-                // Set vertex position and UVs
-                vertices[index].position = vec3(x as f32, height * mountain_height, y as f32);
-                vertices[index].uv = vec2(
-                    x as f32 / (terrain_width - 1) as f32,
-                    y as f32 / (terrain_height - 1) as f32,
-                );
-            }
+        if !self.headless {
+            self.window.gl_swap_window();
         }
+    }
 
-        // --- 2. Calculate Normals ---
-        for y in 0..terrain_height {
-            for x in 0..terrain_width {
-                let index = (y * terrain_width + x) as usize;
-
-                // Default normal pointing straight up.
-                let mut normal = Vec3::Y;
-
-                // Compute normal using the central difference method, if not on an edge.
-                if x > 0 && x < terrain_width - 1 && y > 0 && y < terrain_height - 1 {
-                    let left = vertices[index - 1].position;
-                    let right = vertices[index + 1].position;
-                    let down = vertices[index - terrain_width as usize].position;
-                    let up = vertices[index + terrain_width as usize].position;
-
-                    let dx = right - left;
-                    let dy = up - down; // In C, you had up - down, which is conventional too.
-
-                    normal = dx.cross(dy).normalize();
-                }
-
-                vertices[index].normal = normal;
+    // Reads the last finished frame back, top row first. Call before `end_frame`, the
+    // window's back buffer is undefined after a swap.
+    pub fn read_frame(&self) -> image::RgbaImage {
+        let (width, height) = self.size();
+        let mut pixels = vec![0u8; (width * height * 4) as usize];
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.framebuffer);
+            if self.framebuffer == 0 {
+                gl::ReadBuffer(gl::BACK);
             }
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(0, 0, width as i32, height as i32, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr() as *mut c_void);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
         }
+        let mut frame = image::RgbaImage::from_raw(width, height, pixels).expect("frame buffer size matches the window");
+        // GL rows start at the bottom.
+        image::imageops::flip_vertical_in_place(&mut frame);
+        frame
+    }
 
-        // --- 3. Generate Indices ---
-        for y in 0..terrain_height - 1 {
-            for x in 0..terrain_width - 1 {
-                let top_left = y * terrain_width + x;
-                let top_right = top_left + 1;
-                let bottom_left = (y + 1) * terrain_width + x;
-                let bottom_right = bottom_left + 1;
-
-                // First triangle (top-left, bottom-left, top-right)
-                indices.push(top_left);
-                indices.push(bottom_left);
-                indices.push(top_right);
-
-                // Second triangle (top-right, bottom-left, bottom-right)
-                indices.push(top_right);
-                indices.push(bottom_left);
-                indices.push(bottom_right);
-            }
-        }
-      Graphics::create_mesh(vertices, indices)
+    pub fn capture_frame(&self, path: &str) -> Result<(), String> {
+        self.read_frame().save(path).map_err(|e| format!("Failed to save frame {}: {}", path, e))
     }
     pub fn create_mesh(vertices: Vec<Vertex>, indices: Vec<u32>) -> Mesh {
         let mut vao = 0;
@@ -201,7 +154,7 @@ impl Graphics {
             gl::GenBuffers(1, &mut vbo);
             gl::GenBuffers(1, &mut ebo);
 
-            gl::BindVertexArray(vao);
+            render_state::bind_vertex_array(vao);
 
             gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
             gl::BufferData(
@@ -229,10 +182,11 @@ impl Graphics {
             gl::VertexAttribPointer(2, 2, gl::FLOAT, gl::FALSE, std::mem::size_of::<Vertex>() as i32, (6 * std::mem::size_of::<f32>()) as *const c_void);
             gl::EnableVertexAttribArray(2);
 
-            gl::BindVertexArray(0);
+            render_state::bind_vertex_array(0);
         }
 
         Mesh {
+            bounds: Aabb::from_vertices(&vertices),
             vertices,
             indices,
             vao,
@@ -241,92 +195,363 @@ impl Graphics {
         }
     }
 
+    // Replaces the index list of an existing mesh, e.g. to switch terrain level of detail.
+    pub fn update_mesh_indices(mesh: &mut Mesh, indices: Vec<u32>) {
+        unsafe {
+            render_state::bind_vertex_array(mesh.vao);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, mesh.ebo);
+            gl::BufferData(
+                gl::ELEMENT_ARRAY_BUFFER,
+                (indices.len() * std::mem::size_of::<u32>()) as isize,
+                indices.as_ptr() as *const c_void,
+                gl::STATIC_DRAW,
+            );
+            render_state::bind_vertex_array(0);
+        }
+        mesh.indices = indices;
+    }
+
+    pub fn delete_mesh(mesh: &Mesh) {
+        unsafe {
+            render_state::delete_vertex_array(mesh.vao);
+            gl::DeleteBuffers(1, &mesh.vbo);
+            gl::DeleteBuffers(1, &mesh.ebo);
+        }
+    }
+
+    // Creates a uniform buffer of `T`-sized storage attached to `binding`.
+    pub fn create_uniform_buffer<T>(binding: u32) -> u32 {
+        let mut ubo = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut ubo);
+            gl::BindBuffer(gl::UNIFORM_BUFFER, ubo);
+            gl::BufferData(gl::UNIFORM_BUFFER, std::mem::size_of::<T>() as isize, ptr::null(), gl::DYNAMIC_DRAW);
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, binding, ubo);
+            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+        }
+        ubo
+    }
+
+    pub fn update_uniform_buffer<T>(ubo: u32, data: &T) {
+        unsafe {
+            gl::BindBuffer(gl::UNIFORM_BUFFER, ubo);
+            gl::BufferSubData(gl::UNIFORM_BUFFER, 0, std::mem::size_of::<T>() as isize, data as *const T as *const c_void);
+            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+        }
+    }
+
+    // Storage buffers need OpenGL 4.3, see `ProgramBuilder::compute`. The demo has no compute
+    // pass yet, shader.rs tests run these.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn create_storage_buffer<T: Copy>(binding: u32, data: &[T]) -> u32 {
+        let mut ssbo = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut ssbo);
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, ssbo);
+            gl::BufferData(gl::SHADER_STORAGE_BUFFER, std::mem::size_of_val(data) as isize, data.as_ptr() as *const c_void, gl::DYNAMIC_COPY);
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, binding, ssbo);
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        }
+        ssbo
+    }
+
+    // Overwrites the buffer from element `offset` on.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn update_storage_buffer<T: Copy>(ssbo: u32, offset: usize, data: &[T]) {
+        unsafe {
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, ssbo);
+            gl::BufferSubData(gl::SHADER_STORAGE_BUFFER, (offset * std::mem::size_of::<T>()) as isize, std::mem::size_of_val(data) as isize, data.as_ptr() as *const c_void);
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        }
+    }
+
+    // Reads `count` elements back, after a `render_state::memory_barrier` with
+    // gl::BUFFER_UPDATE_BARRIER_BIT when a dispatch wrote them.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn read_storage_buffer<T: Copy + Default>(ssbo: u32, count: usize) -> Vec<T> {
+        let mut data = vec![T::default(); count];
+        unsafe {
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, ssbo);
+            gl::GetBufferSubData(gl::SHADER_STORAGE_BUFFER, 0, std::mem::size_of_val(data.as_slice()) as isize, data.as_mut_ptr() as *mut c_void);
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        }
+        data
+    }
+
+    // Points a storage buffer binding, the `binding` of a `buffer` block, at `ssbo`.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn bind_storage_buffer(binding: u32, ssbo: u32) {
+        unsafe {
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, binding, ssbo);
+        }
+    }
+
+    // Version of the current context, as (major, minor).
+    pub fn gl_version() -> (i32, i32) {
+        let (mut major, mut minor) = (0, 0);
+        unsafe {
+            gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+            gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+        }
+        (major, minor)
+    }
+
+    pub fn bind_texture(unit: u32, texture: &Texture) {
+        render_state::bind_texture(unit, gl::TEXTURE_2D, texture.id);
+    }
+
+    // Loads an equirectangular (e.g. .hdr) panorama and resamples it into a floating point cubemap.
+    pub fn load_equirectangular_cubemap(path: &str, face_size: u32) -> Result<Skybox, String> {
+        let faces = equirectangular_to_cube_faces(path, face_size)?;
+        Ok(Skybox { cubemap_id: Graphics::create_hdr_cubemap(face_size, &[faces]) })
+    }
+
+    // Uploads RGB float faces (GL order) as an RGB16F cubemap. `levels[0]` is the base level,
+    // every further entry is the next mip, half the size of the previous one.
+    pub fn create_hdr_cubemap(face_size: u32, levels: &[[Vec<f32>; 6]]) -> u32 {
+        let mut id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut id);
+            render_state::bind_texture(0, gl::TEXTURE_CUBE_MAP, id);
+            for (level, faces) in levels.iter().enumerate() {
+                let size = (face_size >> level).max(1);
+                for (i, face) in faces.iter().enumerate() {
+                    gl::TexImage2D(
+                        gl::TEXTURE_CUBE_MAP_POSITIVE_X + i as u32,
+                        level as i32,
+                        gl::RGB16F as i32,
+                        size as i32,
+                        size as i32,
+                        0,
+                        gl::RGB,
+                        gl::FLOAT,
+                        face.as_ptr() as *const c_void,
+                    );
+                }
+            }
+        }
+        Graphics::set_cubemap_parameters(levels.len() as u32);
+        id
+    }
+
+    fn set_cubemap_parameters(mip_levels: u32) {
+        unsafe {
+            let min_filter = if mip_levels > 1 { gl::LINEAR_MIPMAP_LINEAR } else { gl::LINEAR };
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, min_filter as i32);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAX_LEVEL, mip_levels.max(1) as i32 - 1);
+            gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+        }
+    }
+
+    // UV sphere of radius 1 around the origin.
+    pub fn create_sphere_mesh(segments: u32, rings: u32) -> Mesh {
+        let mut vertices = Vec::with_capacity(((segments + 1) * (rings + 1)) as usize);
+        for ring in 0..=rings {
+            let v = ring as f32 / rings as f32;
+            let theta = v * std::f32::consts::PI;
+            for segment in 0..=segments {
+                let u = segment as f32 / segments as f32;
+                let phi = u * std::f32::consts::TAU;
+                let normal = vec3(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                vertices.push(Vertex { position: normal, normal, uv: vec2(u, v) });
+            }
+        }
+        let mut indices = Vec::with_capacity((segments * rings * 6) as usize);
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let top_left = ring * (segments + 1) + segment;
+                let bottom_left = top_left + segments + 1;
+                indices.extend_from_slice(&[top_left, top_left + 1, bottom_left, top_left + 1, bottom_left + 1, bottom_left]);
+            }
+        }
+        Graphics::create_mesh(vertices, indices)
+    }
+
+    // Unit cube drawn from the inside by the skybox pass.
+    pub fn create_skybox_mesh() -> Mesh {
+        let mut vertices = Vec::with_capacity(8);
+        for i in 0..8 {
+            let position = vec3(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { -1.0 } else { 1.0 },
+            );
+            vertices.push(Vertex { position, ..Vertex::default() });
+        }
+        let indices = vec![
+            1, 3, 7, 1, 7, 5, // +X
+            0, 4, 6, 0, 6, 2, // -X
+            2, 6, 7, 2, 7, 3, // +Y
+            0, 1, 5, 0, 5, 4, // -Y
+            4, 5, 7, 4, 7, 6, // +Z
+            0, 2, 3, 0, 3, 1, // -Z
+        ];
+        Graphics::create_mesh(vertices, indices)
+    }
+
+    // Color attachment for offscreen passes, sampled with linear filtering.
+    pub fn create_render_texture(width: u32, height: u32, internal_format: u32) -> u32 {
+        let mut id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut id);
+            render_state::bind_texture(0, gl::TEXTURE_2D, id);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, internal_format as i32, width as i32, height as i32, 0, gl::RGBA, gl::FLOAT, ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            render_state::bind_texture(0, gl::TEXTURE_2D, 0);
+        }
+        id
+    }
+
+    // Depth attachment that later passes can also sample.
+    pub fn create_depth_texture(width: u32, height: u32) -> u32 {
+        let mut id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut id);
+            render_state::bind_texture(0, gl::TEXTURE_2D, id);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH_COMPONENT24 as i32, width as i32, height as i32, 0, gl::DEPTH_COMPONENT, gl::FLOAT, ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            render_state::bind_texture(0, gl::TEXTURE_2D, 0);
+        }
+        id
+    }
+
+    // Framebuffer drawing into `colors` (in attachment order) with an optional depth texture.
+    pub fn create_framebuffer(colors: &[u32], depth: Option<u32>) -> Result<u32, String> {
+        let mut fbo = 0;
+        unsafe {
+            gl::GenFramebuffers(1, &mut fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+            let mut attachments = Vec::with_capacity(colors.len());
+            for (i, texture) in colors.iter().enumerate() {
+                let attachment = gl::COLOR_ATTACHMENT0 + i as u32;
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, attachment, gl::TEXTURE_2D, *texture, 0);
+                attachments.push(attachment);
+            }
+            gl::DrawBuffers(attachments.len() as GLsizei, attachments.as_ptr());
+            if let Some(depth) = depth {
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, depth, 0);
+            }
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                gl::DeleteFramebuffers(1, &fbo);
+                return Err(format!("Framebuffer incomplete: 0x{:x}", status));
+            }
+        }
+        Ok(fbo)
+    }
+
     pub fn load_texture(path: &str) -> Result<Texture, String> {
         let img = image::open(path).map_err(|e| e.to_string())?.into_rgb8();
         let (width, height) = img.dimensions();
         let data = img.into_raw();
 
+        Ok(Graphics::create_texture(width, height, gl::RGB, gl::UNSIGNED_BYTE, &data))
+    }
+
+    // Falls back to a 1x1 texture of `color` when `path` cannot be loaded, for assets the
+    // repository does not ship.
+    pub fn load_texture_or(path: &str, color: [u8; 3]) -> Texture {
+        Graphics::load_texture(path).unwrap_or_else(|e| {
+            println!("Failed to load texture {}: {}, using a solid color", path, e);
+            Graphics::create_texture(1, 1, gl::RGB, gl::UNSIGNED_BYTE, &color)
+        })
+    }
+
+    pub fn create_texture(width: u32, height: u32, format: u32, data_type: u32, data: &[u8]) -> Texture {
+        // BGR(A) is only valid as a pixel transfer format, the texture itself is stored as RGB(A).
+        let internal_format = match format {
+            gl::BGR => gl::RGB,
+            gl::BGRA => gl::RGBA,
+            other => other,
+        };
+
         let mut id = 0;
         unsafe {
             gl::GenTextures(1, &mut id);
-            gl::BindTexture(gl::TEXTURE_2D, id);
+            render_state::bind_texture(0, gl::TEXTURE_2D, id);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
 
+            // Rows of single/dual channel images are not necessarily 4-byte aligned.
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
             gl::TexImage2D(
                 gl::TEXTURE_2D,
                 0,
-                gl::RGB as i32,
+                internal_format as i32,
                 width as i32,
                 height as i32,
                 0,
-                gl::RGB,
-                gl::UNSIGNED_BYTE,
+                format,
+                data_type,
                 data.as_ptr() as *const c_void,
             );
             gl::GenerateMipmap(gl::TEXTURE_2D);
         }
-        Ok(Texture { id })
+        Texture { id }
     }
 }
 
-pub fn load_shader(vs_path: &str, fs_path: &str) -> Result<Shader, String> {
-    let vs_src = fs::read_to_string(vs_path).map_err(|e| format!("Failed to read vertex shader {}: {}", vs_path, e))?;
-    let fs_src = fs::read_to_string(fs_path).map_err(|e| format!("Failed to read fragment shader {}: {}", fs_path, e))?;
-
-    unsafe {
-        let vs = compile_shader(&vs_src, gl::VERTEX_SHADER)?;
-        let fs = compile_shader(&fs_src, gl::FRAGMENT_SHADER)?;
-        let program = link_program(vs, fs)?;
-        gl::DeleteShader(vs);
-        gl::DeleteShader(fs);
-        Ok(Shader { id: program })
-    }
+// Direction through the center of texel (x, y) of a cube face, following the GL face orientation.
+pub fn cube_face_direction(face: usize, x: u32, y: u32, face_size: u32) -> Vec3 {
+    let u = 2.0 * (x as f32 + 0.5) / face_size as f32 - 1.0;
+    let v = 2.0 * (y as f32 + 0.5) / face_size as f32 - 1.0;
+    let direction = match face {
+        0 => vec3(1.0, -v, -u),
+        1 => vec3(-1.0, -v, u),
+        2 => vec3(u, 1.0, v),
+        3 => vec3(u, -1.0, -v),
+        4 => vec3(u, -v, 1.0),
+        _ => vec3(-u, -v, -1.0),
+    };
+    direction.normalize()
 }
 
-// Helper functions for loading shaders
-fn compile_shader(src: &str, ty: gl::types::GLenum) -> Result<u32, String> {
-    unsafe {
-        let shader = gl::CreateShader(ty);
-        let c_str = CString::new(src.as_bytes()).unwrap();
-        gl::ShaderSource(shader, 1, &c_str.as_ptr(), std::ptr::null());
-        gl::CompileShader(shader);
-
-        let mut success = 0;
-        gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
-        if success == 0 {
-            let mut len = 0;
-            gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
-            let mut info_log = Vec::with_capacity(len as usize);
-            info_log.set_len(len as usize);
-            gl::GetShaderInfoLog(shader, len, std::ptr::null_mut(), info_log.as_mut_ptr() as *mut i8);
-            return Err(String::from_utf8_lossy(&info_log).to_string());
-        }
-        Ok(shader)
-    }
+// Bilinear lookup of an equirectangular panorama (row 0 at the top) in a world direction.
+pub fn sample_equirectangular(image: &image::Rgb32FImage, direction: Vec3) -> Vec3 {
+    let (width, height) = image.dimensions();
+    let u = direction.z.atan2(direction.x) / std::f32::consts::TAU + 0.5;
+    let v = 0.5 - direction.y.clamp(-1.0, 1.0).asin() / std::f32::consts::PI;
+
+    let x = u * width as f32 - 0.5;
+    let y = (v * height as f32 - 0.5).clamp(0.0, (height - 1) as f32);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let texel = |x: f32, y: f32| {
+        // Wrap horizontally around the panorama seam.
+        let x = (x as i64).rem_euclid(width as i64) as u32;
+        let y = (y as u32).min(height - 1);
+        Vec3::from(image.get_pixel(x, y).0)
+    };
+    let top = texel(x0, y0).lerp(texel(x0 + 1.0, y0), fx);
+    let bottom = texel(x0, y0 + 1.0).lerp(texel(x0 + 1.0, y0 + 1.0), fx);
+    top.lerp(bottom, fy)
 }
 
-fn link_program(vs: u32, fs: u32) -> Result<u32, String> {
-    unsafe {
-        let program = gl::CreateProgram();
-        gl::AttachShader(program, vs);
-        gl::AttachShader(program, fs);
-        gl::LinkProgram(program);
-
-        let mut success = 0;
-        gl::GetProgramiv(program, gl::LINK_STATUS, &mut success);
-        if success == 0 {
-            let mut len = 0;
-            gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
-            let mut info_log = Vec::with_capacity(len as usize);
-            info_log.set_len(len as usize);
-            gl::GetProgramInfoLog(program, len, std::ptr::null_mut(), info_log.as_mut_ptr() as *mut i8);
-            return Err(String::from_utf8_lossy(&info_log).to_string());
+pub fn equirectangular_to_cube_faces(path: &str, face_size: u32) -> Result<[Vec<f32>; 6], String> {
+    let image = image::open(path).map_err(|e| format!("Failed to load panorama {}: {}", path, e))?.into_rgb32f();
+    Ok(std::array::from_fn(|face| {
+        let mut data = Vec::with_capacity((face_size * face_size * 3) as usize);
+        for y in 0..face_size {
+            for x in 0..face_size {
+                let color = sample_equirectangular(&image, cube_face_direction(face, x, y, face_size));
+                data.extend_from_slice(&color.to_array());
+            }
         }
-        Ok(program)
-    }
+        data
+    }))
 }
 
 // OpenGL Debug Callback
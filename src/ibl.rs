@@ -0,0 +1,334 @@
+use std::f32::consts::{PI, TAU};
+use std::ffi::c_void;
+use std::fs;
+use std::time::UNIX_EPOCH;
+use glam::{vec2, vec3, Vec2, Vec3};
+use image::imageops::{self, FilterType};
+use image::Rgb32FImage;
+use crate::components::Environment;
+use crate::graphics::{cube_face_direction, sample_equirectangular, Graphics};
+use crate::render_state;
+
+pub const IRRADIANCE_SIZE: u32 = 32;
+pub const PREFILTER_SIZE: u32 = 128;
+// Mip count of the prefiltered map, keep PREFILTER_MAX_LOD in pbr.frag at PREFILTER_MIPS - 1.
+pub const PREFILTER_MIPS: u32 = 5;
+pub const BRDF_LUT_SIZE: u32 = 128;
+const PREFILTER_SAMPLES: u32 = 64;
+const BRDF_SAMPLES: u32 = 256;
+
+const CACHE_MAGIC: &[u8; 4] = b"AIBL";
+const CACHE_VERSION: u32 = 1;
+
+// CPU side of the precomputed maps, RGB floats per cube face (GL order) and RG floats for the LUT.
+struct IblMaps {
+    irradiance: [Vec<f32>; 6],
+    prefiltered: Vec<[Vec<f32>; 6]>,
+    brdf_lut: Vec<f32>,
+}
+
+impl Environment {
+    // Loads an equirectangular HDR panorama for image based lighting. The precompute is cached
+    // next to the panorama as `<path>.ibl` and only runs again when the panorama changes.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let cache_path = format!("{}.ibl", path);
+        let stamp = source_stamp(path)?;
+        let maps = match read_cache(&cache_path, stamp) {
+            Some(maps) => maps,
+            None => {
+                let maps = precompute(path)?;
+                if let Err(e) = write_cache(&cache_path, stamp, &maps) {
+                    println!("Failed to write IBL cache {}: {}", cache_path, e);
+                }
+                maps
+            }
+        };
+        Ok(Environment::upload(&maps))
+    }
+
+    // The same ambient `color` from every direction, for scenes without a panorama.
+    pub fn uniform(color: Vec3) -> Self {
+        Environment::upload(&IblMaps {
+            irradiance: cube_faces(IRRADIANCE_SIZE, |_| color),
+            prefiltered: (0..PREFILTER_MIPS).map(|mip| cube_faces(PREFILTER_SIZE >> mip, |_| color)).collect(),
+            brdf_lut: brdf_lut(),
+        })
+    }
+
+    fn upload(maps: &IblMaps) -> Self {
+        Environment {
+            irradiance_map: Graphics::create_hdr_cubemap(IRRADIANCE_SIZE, std::slice::from_ref(&maps.irradiance)),
+            prefilter_map: Graphics::create_hdr_cubemap(PREFILTER_SIZE, &maps.prefiltered),
+            brdf_lut: create_brdf_lut_texture(&maps.brdf_lut),
+        }
+    }
+}
+
+fn precompute(path: &str) -> Result<IblMaps, String> {
+    let image = image::open(path).map_err(|e| format!("Failed to load panorama {}: {}", path, e))?.into_rgb32f();
+    let pyramid = build_pyramid(image);
+
+    let sh = sh_coefficients(&pyramid);
+    let irradiance = cube_faces(IRRADIANCE_SIZE, |direction| sh_irradiance(&sh, direction));
+
+    let (width, height) = pyramid[0].dimensions();
+    let texel_solid_angle = 4.0 * PI / (width * height) as f32;
+    let prefiltered = (0..PREFILTER_MIPS)
+        .map(|mip| {
+            let size = PREFILTER_SIZE >> mip;
+            let roughness = mip as f32 / (PREFILTER_MIPS - 1) as f32;
+            if mip == 0 {
+                // Mirror reflections, just resample at the size of a cube texel.
+                let cube_texel_solid_angle = 4.0 * PI / (6 * size * size) as f32;
+                let lod = 0.5 * (cube_texel_solid_angle / texel_solid_angle).log2();
+                cube_faces(size, |direction| sample_pyramid(&pyramid, direction, lod))
+            } else {
+                cube_faces(size, |direction| prefilter(&pyramid, texel_solid_angle, direction, roughness))
+            }
+        })
+        .collect();
+
+    Ok(IblMaps { irradiance, prefiltered, brdf_lut: brdf_lut() })
+}
+
+// Independent of the panorama, RG per texel.
+fn brdf_lut() -> Vec<f32> {
+    let mut brdf_lut = Vec::with_capacity((BRDF_LUT_SIZE * BRDF_LUT_SIZE * 2) as usize);
+    for y in 0..BRDF_LUT_SIZE {
+        for x in 0..BRDF_LUT_SIZE {
+            let n_dot_v = (x as f32 + 0.5) / BRDF_LUT_SIZE as f32;
+            let roughness = (y as f32 + 0.5) / BRDF_LUT_SIZE as f32;
+            brdf_lut.extend_from_slice(&integrate_brdf(n_dot_v, roughness).to_array());
+        }
+    }
+    brdf_lut
+}
+
+fn cube_faces(size: u32, f: impl Fn(Vec3) -> Vec3) -> [Vec<f32>; 6] {
+    std::array::from_fn(|face| {
+        let mut data = Vec::with_capacity((size * size * 3) as usize);
+        for y in 0..size {
+            for x in 0..size {
+                data.extend_from_slice(&f(cube_face_direction(face, x, y, size)).to_array());
+            }
+        }
+        data
+    })
+}
+
+// Halves the panorama until it is small, so wide sample lobes can read pre-averaged texels.
+fn build_pyramid(image: Rgb32FImage) -> Vec<Rgb32FImage> {
+    let mut pyramid = vec![image];
+    loop {
+        let (width, height) = pyramid.last().unwrap().dimensions();
+        if width <= 16 || height <= 8 {
+            break;
+        }
+        let next = imageops::resize(pyramid.last().unwrap(), width / 2, height / 2, FilterType::Triangle);
+        pyramid.push(next);
+    }
+    pyramid
+}
+
+fn sample_pyramid(pyramid: &[Rgb32FImage], direction: Vec3, lod: f32) -> Vec3 {
+    let lod = lod.clamp(0.0, (pyramid.len() - 1) as f32);
+    let level = lod.floor() as usize;
+    let color = sample_equirectangular(&pyramid[level], direction);
+    if level + 1 < pyramid.len() {
+        color.lerp(sample_equirectangular(&pyramid[level + 1], direction), lod - level as f32)
+    } else {
+        color
+    }
+}
+
+// Projects the panorama onto the first 9 spherical harmonics.
+fn sh_coefficients(pyramid: &[Rgb32FImage]) -> [Vec3; 9] {
+    let image = pyramid.iter().find(|level| level.width() <= 256).unwrap_or(pyramid.last().unwrap());
+    let (width, height) = image.dimensions();
+    let mut coefficients = [Vec3::ZERO; 9];
+    for y in 0..height {
+        // Polar angle from +Y, matching the row mapping of `sample_equirectangular`.
+        let theta = PI * (y as f32 + 0.5) / height as f32;
+        let solid_angle = (TAU / width as f32) * (PI / height as f32) * theta.sin();
+        for x in 0..width {
+            let phi = TAU * (x as f32 + 0.5) / width as f32 - PI;
+            let direction = vec3(phi.cos() * theta.sin(), theta.cos(), phi.sin() * theta.sin());
+            let color = Vec3::from(image.get_pixel(x, y).0);
+            for (coefficient, basis) in coefficients.iter_mut().zip(sh_basis(direction)) {
+                *coefficient += color * basis * solid_angle;
+            }
+        }
+    }
+    coefficients
+}
+
+fn sh_basis(d: Vec3) -> [f32; 9] {
+    [
+        0.282095,
+        0.488603 * d.y,
+        0.488603 * d.z,
+        0.488603 * d.x,
+        1.092548 * d.x * d.y,
+        1.092548 * d.y * d.z,
+        0.315392 * (3.0 * d.z * d.z - 1.0),
+        1.092548 * d.x * d.z,
+        0.546274 * (d.x * d.x - d.y * d.y),
+    ]
+}
+
+// Cosine convolved radiance divided by PI, so the shader only multiplies by albedo.
+fn sh_irradiance(coefficients: &[Vec3; 9], direction: Vec3) -> Vec3 {
+    // Clamped cosine lobe per band (Ramamoorthi and Hanrahan).
+    const BAND: [f32; 9] = [PI, TAU / 3.0, TAU / 3.0, TAU / 3.0, PI / 4.0, PI / 4.0, PI / 4.0, PI / 4.0, PI / 4.0];
+    let mut irradiance = Vec3::ZERO;
+    for ((coefficient, basis), band) in coefficients.iter().zip(sh_basis(direction)).zip(BAND) {
+        irradiance += *coefficient * basis * band;
+    }
+    (irradiance / PI).max(Vec3::ZERO)
+}
+
+// GGX importance sampled convolution with N = V = R. Each sample reads the pyramid level whose
+// texels cover about the solid angle of the sample, which removes most of the noise.
+fn prefilter(pyramid: &[Rgb32FImage], texel_solid_angle: f32, normal: Vec3, roughness: f32) -> Vec3 {
+    let alpha = roughness * roughness;
+    let (tangent, bitangent) = normal.any_orthonormal_pair();
+    let mut color = Vec3::ZERO;
+    let mut total_weight = 0.0;
+    for i in 0..PREFILTER_SAMPLES {
+        let half = importance_sample_ggx(hammersley(i, PREFILTER_SAMPLES), alpha, normal, tangent, bitangent);
+        let light = 2.0 * normal.dot(half) * half - normal;
+        let n_dot_l = normal.dot(light);
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+        let pdf = distribution_ggx(normal.dot(half).max(0.0), alpha) / 4.0;
+        let sample_solid_angle = 1.0 / (PREFILTER_SAMPLES as f32 * pdf + 1e-4);
+        let lod = 0.5 * (sample_solid_angle / texel_solid_angle).log2() + 1.0;
+        color += sample_pyramid(pyramid, light, lod) * n_dot_l;
+        total_weight += n_dot_l;
+    }
+    color / total_weight.max(1e-4)
+}
+
+// Split sum scale and bias applied to F0 for a view angle and roughness.
+fn integrate_brdf(n_dot_v: f32, roughness: f32) -> Vec2 {
+    let view = vec3((1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v);
+    let alpha = roughness * roughness;
+    // Geometry term remapping for image based lighting.
+    let k = alpha / 2.0;
+    let mut scale = 0.0;
+    let mut bias = 0.0;
+    for i in 0..BRDF_SAMPLES {
+        let half = importance_sample_ggx(hammersley(i, BRDF_SAMPLES), alpha, Vec3::Z, Vec3::X, Vec3::Y);
+        let light = 2.0 * view.dot(half) * half - view;
+        let n_dot_l = light.z.max(0.0);
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+        let n_dot_h = half.z.max(0.0);
+        let v_dot_h = view.dot(half).max(0.0);
+        let geometry = (n_dot_v / (n_dot_v * (1.0 - k) + k)) * (n_dot_l / (n_dot_l * (1.0 - k) + k));
+        let visibility = geometry * v_dot_h / (n_dot_h * n_dot_v).max(1e-4);
+        let fresnel = (1.0 - v_dot_h).powi(5);
+        scale += (1.0 - fresnel) * visibility;
+        bias += fresnel * visibility;
+    }
+    vec2(scale, bias) / BRDF_SAMPLES as f32
+}
+
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * denominator * denominator)
+}
+
+fn importance_sample_ggx(xi: Vec2, alpha: f32, normal: Vec3, tangent: Vec3, bitangent: Vec3) -> Vec3 {
+    let phi = TAU * xi.x;
+    let cos_theta = ((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    (tangent * phi.cos() * sin_theta + bitangent * phi.sin() * sin_theta + normal * cos_theta).normalize()
+}
+
+fn hammersley(i: u32, count: u32) -> Vec2 {
+    vec2(i as f32 / count as f32, i.reverse_bits() as f32 * 2.328_306_4e-10)
+}
+
+fn create_brdf_lut_texture(data: &[f32]) -> u32 {
+    let mut id = 0;
+    unsafe {
+        gl::GenTextures(1, &mut id);
+        render_state::bind_texture(0, gl::TEXTURE_2D, id);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::RG16F as i32,
+            BRDF_LUT_SIZE as i32,
+            BRDF_LUT_SIZE as i32,
+            0,
+            gl::RG,
+            gl::FLOAT,
+            data.as_ptr() as *const c_void,
+        );
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        render_state::bind_texture(0, gl::TEXTURE_2D, 0);
+    }
+    id
+}
+
+// Size and modification time of the panorama, a changed file invalidates the cache.
+fn source_stamp(path: &str) -> Result<(u64, u64), String> {
+    let metadata = fs::metadata(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs());
+    Ok((metadata.len(), modified))
+}
+
+// Cache layout: magic, version, source size and mtime (u64), the four map sizes (u32),
+// then the irradiance faces, the prefiltered mips and the LUT as little endian f32.
+fn cache_header(stamp: (u64, u64)) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend_from_slice(CACHE_MAGIC);
+    header.extend_from_slice(&CACHE_VERSION.to_le_bytes());
+    header.extend_from_slice(&stamp.0.to_le_bytes());
+    header.extend_from_slice(&stamp.1.to_le_bytes());
+    for size in [IRRADIANCE_SIZE, PREFILTER_SIZE, PREFILTER_MIPS, BRDF_LUT_SIZE] {
+        header.extend_from_slice(&size.to_le_bytes());
+    }
+    header
+}
+
+fn write_cache(path: &str, stamp: (u64, u64), maps: &IblMaps) -> std::io::Result<()> {
+    let mut bytes = cache_header(stamp);
+    let floats = maps.irradiance.iter().chain(maps.prefiltered.iter().flatten()).flatten().chain(&maps.brdf_lut);
+    for value in floats {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fs::write(path, bytes)
+}
+
+fn read_cache(path: &str, stamp: (u64, u64)) -> Option<IblMaps> {
+    let bytes = fs::read(path).ok()?;
+    let body = bytes.strip_prefix(cache_header(stamp).as_slice())?;
+
+    let face_len = |size: u32| (size * size * 3) as usize;
+    let prefiltered_len: usize = (0..PREFILTER_MIPS).map(|mip| 6 * face_len(PREFILTER_SIZE >> mip)).sum();
+    let lut_len = (BRDF_LUT_SIZE * BRDF_LUT_SIZE * 2) as usize;
+    if body.len() != (6 * face_len(IRRADIANCE_SIZE) + prefiltered_len + lut_len) * 4 {
+        return None;
+    }
+
+    let mut floats = body.chunks_exact(4).map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+    let mut take = |len: usize| floats.by_ref().take(len).collect::<Vec<f32>>();
+    let irradiance = std::array::from_fn(|_| take(face_len(IRRADIANCE_SIZE)));
+    let prefiltered = (0..PREFILTER_MIPS)
+        .map(|mip| std::array::from_fn(|_| take(face_len(PREFILTER_SIZE >> mip))))
+        .collect();
+    let brdf_lut = take(lut_len);
+    Some(IblMaps { irradiance, prefiltered, brdf_lut })
+}
@@ -0,0 +1,151 @@
+use std::ffi::c_void;
+use gl::types::GLsizei;
+use glam::{Mat4, Vec3, Vec4};
+use crate::components::{AlphaMode, Material, Mesh};
+use crate::shader::Shader;
+use crate::render_state;
+
+// First of the four attribute locations (one per column) of `instanceModel` in pbr.vert.
+pub const INSTANCE_MODEL_LOCATION: u32 = 3;
+
+// Meshes sharing a VAO, shader and material, drawn together.
+struct Batch {
+    vao: u32,
+    index_count: GLsizei,
+    shader: Shader,
+    material: Material,
+    models: Vec<Mat4>,
+}
+
+// A mesh with a blended material, drawn on its own once the batches are done.
+struct Blended {
+    vao: u32,
+    index_count: GLsizei,
+    shader: Shader,
+    material: Material,
+    model: Mat4,
+    // World space center of the mesh bounds, sorted on.
+    center: Vec3,
+}
+
+// Collects the meshes drawn over a pass and issues one instanced draw per batch, with the
+// model matrices streamed through a single instance buffer. Entities share a mesh when their
+// `Mesh` components are clones of the same `Graphics::create_mesh` result.
+//
+// Meshes with an `AlphaMode::Blend` material are not batched: they are drawn after the batches,
+// one at a time and back to front, so they composite over everything behind them.
+pub struct InstanceBatches {
+    buffer: u32,
+    batches: Vec<Batch>,
+    blended: Vec<Blended>,
+}
+
+impl InstanceBatches {
+    pub fn new() -> Self {
+        let mut buffer = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut buffer);
+        }
+        Self {
+            buffer,
+            batches: Vec::new(),
+            blended: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, mesh: &Mesh, shader: &Shader, material: &Material, model: Mat4) {
+        if material.alpha_mode == AlphaMode::Blend {
+            self.blended.push(Blended {
+                vao: mesh.vao,
+                index_count: mesh.indices.len() as GLsizei,
+                shader: shader.clone(),
+                material: *material,
+                model,
+                center: model.transform_point3((mesh.bounds.min + mesh.bounds.max) * 0.5),
+            });
+            return;
+        }
+        let batch = self.batches.iter_mut().find(|batch| {
+            batch.vao == mesh.vao && batch.shader.id == shader.id && batch.material == *material
+        });
+        match batch {
+            Some(batch) => batch.models.push(model),
+            None => self.batches.push(Batch {
+                vao: mesh.vao,
+                index_count: mesh.indices.len() as GLsizei,
+                shader: shader.clone(),
+                material: *material,
+                models: vec![model],
+            }),
+        }
+    }
+
+    // Draws and empties every batch, then the blended meshes sorted away from `eye`. Returns the
+    // number of draw calls issued.
+    pub fn flush(&mut self, eye: Vec3) -> u32 {
+        // Batches stay allocated between frames, drop the ones nothing was pushed to.
+        self.batches.retain(|batch| !batch.models.is_empty());
+
+        let mut draw_calls = 0;
+        for batch in &mut self.batches {
+            batch.shader.use_program();
+            batch.material.bind(&batch.shader);
+            render_state::bind_vertex_array(batch.vao);
+            if is_instanced(&batch.shader) {
+                batch.shader.set_uniform("instanced", true);
+                unsafe {
+                    gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer);
+                    gl::BufferData(
+                        gl::ARRAY_BUFFER,
+                        (batch.models.len() * std::mem::size_of::<Mat4>()) as isize,
+                        batch.models.as_ptr() as *const c_void,
+                        gl::STREAM_DRAW,
+                    );
+                    for column in 0..4 {
+                        let location = INSTANCE_MODEL_LOCATION + column;
+                        gl::VertexAttribPointer(location, 4, gl::FLOAT, gl::FALSE, std::mem::size_of::<Mat4>() as i32, (column as usize * std::mem::size_of::<Vec4>()) as *const c_void);
+                        gl::VertexAttribDivisor(location, 1);
+                        gl::EnableVertexAttribArray(location);
+                    }
+                }
+                render_state::draw_elements_instanced(batch.index_count, batch.models.len() as GLsizei);
+                // The VAO is shared with draws that read `model` instead, leave it as it was.
+                unsafe {
+                    for column in 0..4 {
+                        let location = INSTANCE_MODEL_LOCATION + column;
+                        gl::VertexAttribDivisor(location, 0);
+                        gl::DisableVertexAttribArray(location);
+                    }
+                }
+                draw_calls += 1;
+            } else {
+                // Vertex shaders without `instanceModel` draw one mesh at a time.
+                for model in &batch.models {
+                    batch.shader.set_uniform("model", *model);
+                    render_state::draw_elements(batch.index_count);
+                }
+                draw_calls += batch.models.len() as u32;
+            }
+            batch.models.clear();
+        }
+
+        self.blended.sort_by(|a, b| b.center.distance_squared(eye).total_cmp(&a.center.distance_squared(eye)));
+        for blended in self.blended.drain(..) {
+            blended.shader.use_program();
+            blended.material.bind(&blended.shader);
+            if is_instanced(&blended.shader) {
+                blended.shader.set_uniform("instanced", false);
+            }
+            blended.shader.set_uniform("model", blended.model);
+            render_state::bind_vertex_array(blended.vao);
+            render_state::draw_elements(blended.index_count);
+            draw_calls += 1;
+        }
+        draw_calls
+    }
+}
+
+// Whether the vertex shader reads its model matrix from the instance buffer.
+fn is_instanced(shader: &Shader) -> bool {
+    shader.attribute("instanceModel").is_some_and(|attribute| attribute.location == INSTANCE_MODEL_LOCATION as i32)
+}
@@ -4,13 +4,38 @@ use flecs_ecs::prelude::{Entity, EntityView};
 use glam::{Mat4, Vec2, Vec3};
 use sdl2::event::Event;
 use sdl2::keyboard::{Keycode, Scancode};
-use crate::components::{ActiveCameraData, Camera, Local, Mesh, Position, Rotation, Transform, Vertex};
+use crate::components::{ActiveCameraData, Camera, Emission, Environment, Light, LightKind, Local, Material, RenderPath, ShadowSettings, Position, TerrainLayer, TerrainMaterial, Rotation, Transform, Vertex};
 use crate::ecs::Ecs;
-use crate::graphics::{load_shader, Graphics};
+use crate::gltf_loader::load_gltf;
+use crate::graphics::Graphics;
+use crate::shader::{load_shader, load_shader_with};
+use crate::shader_preprocessor::Defines;
+use crate::shader_watcher::ShaderWatcher;
+use crate::shadows::ShadowRenderer;
+use crate::post::{PostEffect, PostProcess, ToneMapper};
+use crate::deferred::DeferredRenderer;
+use crate::terrain::{Heightfield, TerrainGenerator, TerrainLod, TerrainSettings, TerrainStreamer};
 
 mod graphics;
 mod components;
 mod ecs;
+mod gltf_loader;
+mod terrain;
+mod erosion;
+mod material;
+mod shadows;
+mod bloom;
+mod ibl;
+mod deferred;
+mod post;
+mod culling;
+mod instancing;
+mod render_state;
+mod shader;
+mod shader_preprocessor;
+mod shader_watcher;
+#[cfg(test)]
+mod golden;
 
 const CUBE_VERTICES: [Vertex; 24] = [
     // Front face
@@ -53,71 +78,30 @@ const CUBE_INDICES: [u32; 36] = [
     16, 17, 18, 16, 18, 19, // Bottom
     20, 21, 22, 20, 22, 23, // Top
 ];
-fn get_height_on_terrain(
-    x: f32,
-    z: f32,
-    terrain: &Mesh,
-    terrain_width: u32,
-    terrain_depth: u32,
-) -> f32 {
-    // Find the integer grid coordinates.
-    let grid_x = x as i32;
-    let grid_z = z as i32;
-
-    // --- Boundary Check ---
-    if grid_x < 0
-        || grid_x >= terrain_width as i32 - 1
-        || grid_z < 0
-        || grid_z >= terrain_depth as i32 - 1
-    {
-        return 0.0; // Return a default height if outside the terrain
-    }
-
-    // --- Fractional Coordinates ---
-    // Calculate how far across the grid cell the point is (from 0.0 to 1.0).
-    let frac_x = x - grid_x as f32;
-    let frac_z = z - grid_z as f32;
-
-    // --- Get the Four Corner Vertices of the Quad ---
-    let v00 = terrain.vertices[(grid_z * terrain_width as i32 + grid_x) as usize];
-    let v10 = terrain.vertices[(grid_z * terrain_width as i32 + (grid_x + 1)) as usize];
-    let v01 = terrain.vertices[((grid_z + 1) * terrain_width as i32 + grid_x) as usize];
-    let v11 = terrain.vertices[((grid_z + 1) * terrain_width as i32 + (grid_x + 1)) as usize];
-
-    // --- Bilinear Interpolation ---
-    // Helper function for linear interpolation (lerp).
-    let lerp = |a: f32, b: f32, t: f32| a * (1.0 - t) + b * t;
-
-    // 1. Interpolate in the X direction for the top and bottom edges.
-    let height_top = lerp(v00.position.y, v10.position.y, frac_x);
-    let height_bottom = lerp(v01.position.y, v11.position.y, frac_x);
-
-    // 2. Interpolate in the Z direction between the two results.
-    let final_height = lerp(height_top, height_bottom, frac_z);
-
-    final_height
+// How fast the player walks (units per second) and turns (degrees per pixel of mouse motion).
+pub struct MoveSettings {
+    pub speed: f32,
+    pub sensitivity: f32,
 }
 
 pub fn player_move(
     player: EntityView,
     mouse_delta: Vec2,
     input_axis: Vec2,
-    speed: f32,
-    sensitivity: f32,
+    settings: &MoveSettings,
     dt: f32,
-    terrain: &Mesh,
-    terrain_width: u32,
-    terrain_depth: u32,
+    world: &Ecs,
+    terrain: &TerrainStreamer,
 ) {
     // --- Get Player Components ---
     // We get a copy of the current components from the entity.
     // We'll modify these copies and then write them back at the end.
 
     //let mut pos = *player.get::<&Position>().expect("Player entity must have a Position component");
-    player.get::<(&mut (Transform,Local), &mut Position, &mut Rotation)>(|(transform, pos, rot)| {
+    let (position, front, up) = player.map::<(&mut Position, &mut Rotation), _>(|(pos, rot)| {
 
-        rot.0.y += mouse_delta.x * sensitivity;
-        rot.0.x -= mouse_delta.y * sensitivity;
+        rot.0.y += mouse_delta.x * settings.sensitivity;
+        rot.0.x -= mouse_delta.y * settings.sensitivity;
         rot.0.x = rot.0.x.clamp(-89.0, 89.0);
         let yaw_rad = rot.0.y.to_radians();
         let pitch_rad = rot.0.x.to_radians();
@@ -132,19 +116,21 @@ pub fn player_move(
         let up = right.cross(front).normalize();
 
         // 4. APPLY MOVEMENT based on input axis and direction vectors
-        let move_vertical = front * speed * dt * input_axis.y;
-        let move_horizontal = right * speed * dt * input_axis.x;
+        let move_vertical = front * settings.speed * dt * input_axis.y;
+        let move_horizontal = right * settings.speed * dt * input_axis.x;
         pos.0 += move_vertical + move_horizontal;
+        (pos.0, front, up)
+    });
 
-        // 5. UPDATE PLAYER HEIGHT based on terrain
-        let terrain_height = get_height_on_terrain(
-            pos.0.x,
-            pos.0.z,
-            terrain,
-            terrain_width,
-            terrain_depth
-        );
-        pos.0.y = terrain_height + 10.0; // Eye-level adjustment
+    // 5. UPDATE PLAYER HEIGHT based on terrain
+    // Queried outside the player's components since it reads the terrain entities.
+    let terrain_height = terrain.height_at(world, position.x, position.z);
+
+    player.get::<(&mut (Transform,Local), &mut Position)>(|(transform, pos)| {
+        // Keep the current height over chunks that have not been streamed in yet.
+        if let Some(terrain_height) = terrain_height {
+            pos.0.y = terrain_height + 10.0; // Eye-level adjustment
+        }
 
         // 6. UPDATE ENTITY TRANSFORM for rendering
         let center = pos.0 + front;
@@ -152,15 +138,42 @@ pub fn player_move(
         transform.0 = view_matrix.inverse();
     });
 }
+// Frames rendered before `--capture` saves one, enough for the terrain around the camera to stream in.
+const CAPTURE_FRAMES: u32 = 60;
+// World height of white in a `--heightmap` image.
+const HEIGHTMAP_HEIGHT: f32 = 20.0;
+// Where H saves the terrain under the camera.
+const HEIGHTMAP_EXPORT_PATH: &str = "terrain_export.png";
+
+// Removes `name <value>` from `args` and returns the value.
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    let Some(i) = args.iter().position(|arg| arg == name) else {
+        return Ok(None);
+    };
+    args.remove(i);
+    if i >= args.len() {
+        return Err(format!("{} needs a path", name));
+    }
+    Ok(Some(args.remove(i)))
+}
+
 fn main() -> Result<(), String> {
-    let graphics = Graphics::new("Rust Engine", 1280, 720)?;
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // `--capture <png>` renders headless with a fixed time step and saves a frame instead of opening a window.
+    let capture_path = take_option(&mut args, "--capture")?;
+    // `--heightmap <png>` places a grayscale heightmap at the origin, replacing the generated chunks there.
+    let heightmap_path = take_option(&mut args, "--heightmap")?;
+    let graphics = match capture_path {
+        Some(_) => Graphics::new_headless(1280, 720)?,
+        None => Graphics::new("Rust Engine", 1280, 720)?,
+    };
     let mut event_pump = graphics.sdl_context.event_pump()?;
-    let shader = load_shader("assets/light.vert", "assets/light.frag")?;
+    let shader = load_shader_with("assets/pbr.vert", "assets/pbr.frag", &Defines::new().flag("SHADOWS"))?;
     let cube_mesh = Graphics::create_mesh(CUBE_VERTICES.to_vec(), CUBE_INDICES.to_vec());
     let projection = Mat4::perspective_rh_gl(45.0f32.to_radians(), 1280 as f32 / 720 as f32, 0.1, 100.0);
 
-   let mut world =  Ecs::new();
-    let texture = Graphics::load_texture("assets/marble2.jpg")?;
+   let world =  Ecs::new();
+    let texture = Graphics::load_texture_or("assets/marble2.jpg", [220, 220, 220]);
 
     let cube =  world.create_entity("cube",Vec3::ZERO,Vec3::ONE,Vec3::ZERO,None);
     let camera =  world.create_entity("camera",Vec3 {
@@ -169,36 +182,105 @@ fn main() -> Result<(), String> {
         z: 2.0,
     },Vec3::ONE,Vec3::ZERO,None);
 
-    world.add_pbr_shader(cube,shader);
-    world.add_mesh(cube,cube_mesh, Some(texture));
-    let terrain =  world.create_entity("terrain",Vec3 {
-        x: 0.0,
-        y: 0.0,
-        z: 0.0,
-    },Vec3::ONE,Vec3::ZERO,None);
+    world.add_pbr_shader(cube,shader.clone());
+    world.add_mesh(cube,cube_mesh, Some(Material {
+        base_color_texture: Some(texture),
+        roughness: 0.3,
+        ..Material::default()
+    }));
+    let terrain_shader = load_shader_with("assets/terrain.vert", "assets/terrain.frag", &Defines::new().flag("SHADOWS"))?;
+    let terrain_material = TerrainMaterial {
+        layers: vec![
+            TerrainLayer { texture: Graphics::load_texture_or("assets/sand.jpg", [194, 178, 128]), tiling: 0.25, height: Vec3::new(-100.0, 2.0, 0.5), slope: Vec3::new(0.0, 0.3, 0.1) },
+            TerrainLayer { texture: Graphics::load_texture_or("assets/grass.jpg", [80, 140, 60]), tiling: 0.2, height: Vec3::new(2.0, 7.0, 0.5), slope: Vec3::new(0.0, 0.3, 0.1) },
+            TerrainLayer { texture: Graphics::load_texture_or("assets/rock.jpg", [110, 100, 95]), tiling: 0.1, height: Vec3::new(-100.0, 100.0, 0.0), slope: Vec3::new(0.3, 1.0, 0.1) },
+            TerrainLayer { texture: Graphics::load_texture_or("assets/snow.jpg", [240, 240, 245]), tiling: 0.2, height: Vec3::new(7.0, 100.0, 0.5), slope: Vec3::new(0.0, 0.3, 0.1) },
+        ],
+        splat_map: None,
+    };
+    let mut terrain = TerrainStreamer::new(TerrainGenerator::new(TerrainSettings::default()), 64, 4, TerrainLod::new(2.0, 720, 45.0), terrain_shader, terrain_material);
+    if let Some(path) = &heightmap_path {
+        terrain.add_heightfield(&world, "heightmap", Heightfield::load_png(path, HEIGHTMAP_HEIGHT)?, Vec3::ZERO);
+    }
 
-   let terrrain_mesh =  Graphics::create_terrain(200,200);
-    world.add_pbr_shader(terrain,shader);
-    world.add_mesh(terrain,terrrain_mesh.clone(), Some(texture));
+    // An optional glTF scene can be passed on the command line, e.g. `aurionrs assets/scene.glb`.
+    if let Some(scene_path) = args.first() {
+        load_gltf(&world, scene_path, shader, None)?;
+    }
 
     world.add_camera(camera,Camera {
         projection: projection,
     });
 
-    let (update_system, render_system,camera_system) = world.create_system();
+    let sun = world.create_entity("sun", Vec3::ZERO, Vec3::ONE, Vec3::new(-50.0, 30.0, 0.0), None);
+    world.add_light(sun, Light {
+        color: Vec3::new(1.0, 0.95, 0.85),
+        intensity: 3.0,
+        kind: LightKind::Directional,
+        shadow: Some(ShadowSettings::default()),
+    });
+    let lamp = world.create_entity("lamp", Vec3::new(2.0, 2.0, 2.0), Vec3::ONE, Vec3::ZERO, None);
+    world.add_light(lamp, Light {
+        color: Vec3::new(1.0, 0.6, 0.3),
+        intensity: 20.0,
+        kind: LightKind::Point { range: 15.0 },
+        shadow: None,
+    });
+
+    let orb = world.create_entity("orb", Vec3::new(-3.0, 3.0, 1.0), Vec3::splat(0.3), Vec3::ZERO, None);
+    world.add_mesh(orb, Graphics::create_sphere_mesh(32, 16), None);
+    world.add_emission(orb, Emission {
+        orb_color: Vec3::new(0.3, 0.7, 1.0),
+        intensity: 4.0,
+        center_position: Vec3::ZERO,
+        radius: 0.3,
+    }, load_shader("assets/pbr.vert", "assets/emissive.frag")?);
+
+    let (update_system, render_system,camera_system, light_system) = world.create_system();
+    let shadows = ShadowRenderer::new(&world, 2048, 4)?;
+    // The sky panorama is optional, without it the background stays the clear color.
+    match Graphics::load_equirectangular_cubemap("assets/sky.hdr", 512) {
+        Ok(skybox) => world.set_skybox(skybox),
+        Err(e) => println!("Drawing without a skybox: {}", e),
+    }
+    world.set_environment(Environment::load("assets/sky.hdr").unwrap_or_else(|e| {
+        println!("{}, using a uniform ambient light", e);
+        Environment::uniform(Vec3::splat(0.3))
+    }));
+    let environment_system = world.create_environment_system();
+    let skybox_system = world.create_skybox_system(load_shader("assets/skybox.vert", "assets/skybox.frag")?);
+    let emissive_system = world.create_emissive_system();
+    let mut post = PostProcess::new(1280, 720)?;
+    // Set RENDER_PATH=deferred to light opaque meshes in a G-buffer pass.
+    let mut deferred = match std::env::var("RENDER_PATH").as_deref() {
+        Ok("deferred") => {
+            world.set_render_path(RenderPath::Deferred);
+            Some(DeferredRenderer::new(&world, 1280, 720)?)
+        }
+        _ => None,
+    };
+    // Edits to the PBR, terrain and emissive shader sources are picked up while running.
+    let mut shader_watcher = ShaderWatcher::new(&world);
+    let move_settings = MoveSettings { speed: 25.0, sensitivity: 1.0 };
     let mut last_frame_time = Instant::now();
+    let mut frame = 0;
 
 
     'running: loop {
         // --- Input Handling ---
         let current_time = Instant::now();
-        let dt = (current_time - last_frame_time).as_secs_f32();
+        let dt = match capture_path {
+            Some(_) => 1.0 / 60.0,
+            None => (current_time - last_frame_time).as_secs_f32(),
+        };
         last_frame_time = current_time;
 
+        let stats = world.take_render_stats();
+        let gl_stats = render_state::take_gl_stats();
         if dt > 0.0 { // Avoid printing too fast if the window is frozen
             println!(
-                " FPS: {:.1}",
-                1.0 / dt
+                " FPS: {:.1} draws: {} draw calls: {} culled: {} gl calls: {} (binds: {}, skipped: {}, uniforms: {})",
+                1.0 / dt, stats.drawn, stats.draw_calls, stats.culled, gl_stats.calls(), gl_stats.binds, gl_stats.skipped, gl_stats.uniforms
             );
         }
         let mut mouse_delta = Vec2::ZERO;
@@ -213,6 +295,43 @@ fn main() -> Result<(), String> {
                 } => {
                     break 'running;
                 }
+                // B, F and V toggle bloom, FXAA and the vignette, T switches the tone mapper.
+                Event::KeyDown { keycode: Some(Keycode::T), .. } => {
+                    for effect in post.effects.iter_mut() {
+                        if let PostEffect::ToneMap(tone_mapper) = effect {
+                            *tone_mapper = match tone_mapper {
+                                ToneMapper::Aces => ToneMapper::Reinhard,
+                                ToneMapper::Reinhard => ToneMapper::Aces,
+                            };
+                        }
+                    }
+                }
+                Event::KeyDown { keycode: Some(Keycode::B), .. } => {
+                    let bloom = PostEffect::Bloom { threshold: 1.0, intensity: 0.8, blur_passes: 5 };
+                    if !post.remove(&bloom) {
+                        post.effects.insert(0, bloom);
+                    }
+                }
+                Event::KeyDown { keycode: Some(Keycode::F), .. } => {
+                    let fxaa = PostEffect::Fxaa;
+                    if !post.remove(&fxaa) {
+                        post.effects.push(fxaa);
+                    }
+                }
+                Event::KeyDown { keycode: Some(Keycode::V), .. } => {
+                    let vignette = PostEffect::Vignette { strength: 0.5, radius: 0.3 };
+                    if !post.remove(&vignette) {
+                        post.effects.push(vignette);
+                    }
+                }
+                // H saves the terrain under the camera as a 16-bit heightmap.
+                Event::KeyDown { keycode: Some(Keycode::H), .. } => {
+                    let position = camera.entity_view(&world.world).map::<&Position, _>(|pos| pos.0);
+                    match terrain.export_png(&world, position.x, position.z, HEIGHTMAP_EXPORT_PATH) {
+                        Ok(()) => println!("Saved the terrain under the camera to {}", HEIGHTMAP_EXPORT_PATH),
+                        Err(e) => println!("{}", e),
+                    }
+                }
                 // Capture relative mouse movement
                 Event::MouseMotion { xrel, yrel, .. } => {
                     mouse_delta.x = xrel as f32;
@@ -240,12 +359,32 @@ fn main() -> Result<(), String> {
             input_axis.x = 1.0;
         }
 
-        player_move(camera.entity_view(&world.world),mouse_delta,input_axis,25.0,1.0,dt,&terrrain_mesh,200,200);
+        player_move(camera.entity_view(&world.world),mouse_delta,input_axis,&move_settings,dt,&world,&terrain);
+        let camera_pos = camera.entity_view(&world.world).map::<&Position, _>(|pos| pos.0);
+        terrain.update(&world, camera_pos);
+        shader_watcher.update(|old, shader| terrain.replace_shader(old, shader));
         // --- Logic Update ---
         update_system.run();
         camera_system.run();
+        light_system.run();
+        shadows.render(&world);
+        environment_system.run();
+        post.begin();
         graphics.begin_frame();
+        if let Some(deferred) = &mut deferred {
+            deferred.render(&world);
+        }
         render_system.run();
+        emissive_system.run();
+        skybox_system.run();
+        post.end(graphics.framebuffer());
+        frame += 1;
+        if let Some(path) = &capture_path
+            && frame == CAPTURE_FRAMES
+        {
+            graphics.capture_frame(path)?;
+            break 'running;
+        }
         graphics.end_frame();
     }
     Ok(())
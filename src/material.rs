@@ -0,0 +1,55 @@
+use crate::components::{AlphaMode, Material, Texture};
+use crate::graphics::Graphics;
+use crate::shader::{Shader, TextureUnit};
+
+impl Material {
+    // Uploads the `material` uniform struct of the PBR shader. Texture slots use units 0..4
+    // and the blend state follows the alpha mode.
+    pub fn bind(&self, shader: &Shader) {
+        shader.set_uniform("material.baseColor", self.base_color);
+        shader.set_uniform("material.metallic", self.metallic);
+        shader.set_uniform("material.roughness", self.roughness);
+        shader.set_uniform("material.emissive", self.emissive);
+        shader.set_uniform("material.normalScale", self.normal_scale);
+        shader.set_uniform("material.occlusionStrength", self.occlusion_strength);
+
+        let (alpha_mode, alpha_cutoff) = match self.alpha_mode {
+            AlphaMode::Opaque => (0, 0.0),
+            AlphaMode::Mask(cutoff) => (1, cutoff),
+            AlphaMode::Blend => (2, 0.0),
+        };
+        shader.set_uniform("material.alphaMode", alpha_mode);
+        shader.set_uniform("material.alphaCutoff", alpha_cutoff);
+        unsafe {
+            if self.alpha_mode == AlphaMode::Blend {
+                gl::Enable(gl::BLEND);
+                gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            } else {
+                gl::Disable(gl::BLEND);
+            }
+        }
+
+        let slots = [
+            ("baseColor", self.base_color_texture),
+            ("metallicRoughness", self.metallic_roughness_texture),
+            ("normal", self.normal_texture),
+            ("emissive", self.emissive_texture),
+            ("occlusion", self.occlusion_texture),
+        ];
+        for (unit, (slot, texture)) in slots.iter().enumerate() {
+            bind_slot(shader, unit as u32, slot, texture);
+        }
+    }
+}
+
+fn bind_slot(shader: &Shader, unit: u32, slot: &str, texture: &Option<Texture>) {
+    shader.set_uniform(&format!("material.{}Texture", slot), TextureUnit(unit));
+    let has_texture = format!("material.has{}{}Texture", slot[..1].to_uppercase(), &slot[1..]);
+    match texture {
+        Some(texture) => {
+            Graphics::bind_texture(unit, texture);
+            shader.set_uniform(&has_texture, true);
+        }
+        None => shader.set_uniform(&has_texture, false),
+    }
+}
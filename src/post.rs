@@ -0,0 +1,174 @@
+use std::mem::discriminant;
+use crate::bloom::Bloom;
+use crate::graphics::Graphics;
+use crate::shader::{load_shader, Shader, TextureUnit};
+use crate::render_state;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapper {
+    Reinhard,
+    Aces,
+}
+
+// One step of the post chain. Steps run in order, each reading the output of the previous one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PostEffect {
+    Exposure(f32),
+    ToneMap(ToneMapper),
+    Gamma(f32),
+    Bloom { threshold: f32, intensity: f32, blur_passes: u32 },
+    // Expects gamma corrected input, so keep it after `Gamma`.
+    Fxaa,
+    // `radius` is where darkening starts, 0.5 reaches the screen edges.
+    Vignette { strength: f32, radius: f32 },
+}
+
+// The scene is rendered into an HDR target between `begin` and `end`; `end` runs `effects`
+// and draws the result to `target` (see `Graphics::framebuffer`). Effects can be changed
+// between frames.
+pub struct PostProcess {
+    width: u32,
+    height: u32,
+    pub effects: Vec<PostEffect>,
+    scene_fbo: u32,
+    scene_color: u32,
+    ping_fbos: [u32; 2],
+    ping_textures: [u32; 2],
+    vao: u32,
+    bloom: Bloom,
+    copy: Shader,
+    exposure: Shader,
+    tone_map: Shader,
+    gamma: Shader,
+    fxaa: Shader,
+    vignette: Shader,
+}
+
+impl PostProcess {
+    pub fn new(width: u32, height: u32) -> Result<Self, String> {
+        let post_shader = |fragment: &str| load_shader("assets/fullscreen.vert", &format!("assets/{}", fragment));
+
+        let scene_color = Graphics::create_render_texture(width, height, gl::RGBA16F);
+        let scene_fbo = Graphics::create_framebuffer(&[scene_color], Some(Graphics::create_depth_texture(width, height)))?;
+        let ping_textures = [
+            Graphics::create_render_texture(width, height, gl::RGBA16F),
+            Graphics::create_render_texture(width, height, gl::RGBA16F),
+        ];
+        let ping_fbos = [
+            Graphics::create_framebuffer(&[ping_textures[0]], None)?,
+            Graphics::create_framebuffer(&[ping_textures[1]], None)?,
+        ];
+
+        let mut vao = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+        }
+
+        Ok(Self {
+            width,
+            height,
+            effects: PostProcess::default_effects(),
+            scene_fbo,
+            scene_color,
+            ping_fbos,
+            ping_textures,
+            vao,
+            bloom: Bloom::new(width, height)?,
+            copy: post_shader("post_copy.frag")?,
+            exposure: post_shader("post_exposure.frag")?,
+            tone_map: post_shader("post_tonemap.frag")?,
+            gamma: post_shader("post_gamma.frag")?,
+            fxaa: post_shader("post_fxaa.frag")?,
+            vignette: post_shader("post_vignette.frag")?,
+        })
+    }
+
+    pub fn default_effects() -> Vec<PostEffect> {
+        vec![
+            PostEffect::Bloom { threshold: 1.0, intensity: 0.8, blur_passes: 5 },
+            PostEffect::Exposure(1.0),
+            PostEffect::ToneMap(ToneMapper::Aces),
+            PostEffect::Gamma(2.2),
+            PostEffect::Fxaa,
+        ]
+    }
+
+    // Removes every effect of the same kind as `effect`, whatever its parameters.
+    // Returns false when there was none.
+    pub fn remove(&mut self, effect: &PostEffect) -> bool {
+        let count = self.effects.len();
+        self.effects.retain(|e| discriminant(e) != discriminant(effect));
+        self.effects.len() != count
+    }
+
+    // Redirects rendering into the HDR scene target. Call before `Graphics::begin_frame`.
+    pub fn begin(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.scene_fbo);
+        }
+    }
+
+    pub fn end(&self, target: u32) {
+        unsafe {
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::BLEND);
+        }
+        render_state::bind_vertex_array(self.vao);
+
+        let mut source = self.scene_color;
+        if self.effects.is_empty() {
+            self.draw(&self.copy, source, target);
+        }
+        for (i, effect) in self.effects.iter().enumerate() {
+            // The last step writes to the target, the others alternate between the ping targets.
+            let (target, output) = if i + 1 == self.effects.len() {
+                (target, 0)
+            } else {
+                (self.ping_fbos[i % 2], self.ping_textures[i % 2])
+            };
+            match *effect {
+                PostEffect::Exposure(exposure) => {
+                    self.exposure.use_program();
+                    self.exposure.set_uniform("exposure", exposure);
+                    self.draw(&self.exposure, source, target);
+                }
+                PostEffect::ToneMap(tone_mapper) => {
+                    self.tone_map.use_program();
+                    self.tone_map.set_uniform("toneMapper", tone_mapper as i32);
+                    self.draw(&self.tone_map, source, target);
+                }
+                PostEffect::Gamma(gamma) => {
+                    self.gamma.use_program();
+                    self.gamma.set_uniform("gamma", gamma);
+                    self.draw(&self.gamma, source, target);
+                }
+                PostEffect::Bloom { threshold, intensity, blur_passes } => {
+                    self.bloom.apply(source, target, threshold, intensity, blur_passes);
+                }
+                PostEffect::Fxaa => self.draw(&self.fxaa, source, target),
+                PostEffect::Vignette { strength, radius } => {
+                    self.vignette.use_program();
+                    self.vignette.set_uniform("strength", strength);
+                    self.vignette.set_uniform("radius", radius);
+                    self.draw(&self.vignette, source, target);
+                }
+            }
+            source = output;
+        }
+
+        unsafe {
+            gl::Enable(gl::DEPTH_TEST);
+        }
+    }
+
+    fn draw(&self, shader: &Shader, source: u32, target: u32) {
+        shader.use_program();
+        shader.set_uniform("image", TextureUnit(0));
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, target);
+            gl::Viewport(0, 0, self.width as i32, self.height as i32);
+        }
+        render_state::bind_texture(0, gl::TEXTURE_2D, source);
+        render_state::draw_fullscreen_triangle();
+    }
+}
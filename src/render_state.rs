@@ -0,0 +1,183 @@
+use std::cell::RefCell;
+use std::ptr;
+use gl::types::{GLbitfield, GLsizei};
+
+// Units 0..=SPOT_SHADOW_UNIT are the ones the renderer binds, see graphics.
+const MAX_TEXTURE_UNITS: usize = 16;
+
+// GL calls issued through this module and `Shader` since the last `take_gl_stats`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GlStats {
+    // Program, VAO and texture binds that changed state.
+    pub binds: u32,
+    // Binds left out because the state was already current.
+    pub skipped: u32,
+    pub uniforms: u32,
+    pub draws: u32,
+    pub dispatches: u32,
+}
+
+impl GlStats {
+    pub fn calls(&self) -> u32 {
+        self.binds + self.uniforms + self.draws + self.dispatches
+    }
+}
+
+// Mirror of what the renderer last bound. GL state belongs to the context, which lives on the
+// main thread, hence thread local. Programs, VAOs and textures must be bound through here,
+// otherwise a later bind of the same object may be skipped.
+#[derive(Default)]
+struct State {
+    program: u32,
+    vao: u32,
+    active_unit: u32,
+    // (target, texture) last bound on each unit.
+    textures: [(u32, u32); MAX_TEXTURE_UNITS],
+    stats: GlStats,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+// Binds `program` unless it is current already.
+pub fn use_program(program: u32) {
+    STATE.with_borrow_mut(|state| {
+        if state.program == program {
+            state.stats.skipped += 1;
+            return;
+        }
+        state.program = program;
+        state.stats.binds += 1;
+        unsafe {
+            gl::UseProgram(program);
+        }
+    });
+}
+
+pub fn bind_vertex_array(vao: u32) {
+    STATE.with_borrow_mut(|state| {
+        if state.vao == vao {
+            state.stats.skipped += 1;
+            return;
+        }
+        state.vao = vao;
+        state.stats.binds += 1;
+        unsafe {
+            gl::BindVertexArray(vao);
+        }
+    });
+}
+
+// Binds `texture` to `target` on `unit`, only switching the active unit when needed.
+pub fn bind_texture(unit: u32, target: u32, texture: u32) {
+    STATE.with_borrow_mut(|state| {
+        let slot = &mut state.textures[unit as usize];
+        if *slot == (target, texture) {
+            state.stats.skipped += 1;
+            return;
+        }
+        *slot = (target, texture);
+        unsafe {
+            if state.active_unit != unit {
+                state.active_unit = unit;
+                state.stats.binds += 1;
+                gl::ActiveTexture(gl::TEXTURE0 + unit);
+            }
+            state.stats.binds += 1;
+            gl::BindTexture(target, texture);
+        }
+    });
+}
+
+// Forgets a deleted VAO, GL reuses names and the next one may get the same.
+pub fn delete_vertex_array(vao: u32) {
+    STATE.with_borrow_mut(|state| {
+        if state.vao == vao {
+            state.vao = 0;
+        }
+        unsafe {
+            gl::DeleteVertexArrays(1, &vao);
+        }
+    });
+}
+
+// Forgets a deleted program, GL reuses names and the next one may get the same.
+pub fn delete_program(program: u32) {
+    STATE.with_borrow_mut(|state| {
+        if state.program == program {
+            state.program = 0;
+        }
+        unsafe {
+            gl::DeleteProgram(program);
+        }
+    });
+}
+
+pub fn draw_elements(index_count: GLsizei) {
+    count_draw();
+    unsafe {
+        gl::DrawElements(gl::TRIANGLES, index_count, gl::UNSIGNED_INT, ptr::null());
+    }
+}
+
+pub fn draw_elements_instanced(index_count: GLsizei, instances: GLsizei) {
+    count_draw();
+    unsafe {
+        gl::DrawElementsInstanced(gl::TRIANGLES, index_count, gl::UNSIGNED_INT, ptr::null(), instances);
+    }
+}
+
+// Fullscreen passes draw one triangle generated by fullscreen.vert.
+pub fn draw_fullscreen_triangle() {
+    count_draw();
+    unsafe {
+        gl::DrawArrays(gl::TRIANGLES, 0, 3);
+    }
+}
+
+// Draws a tessellated program, every `patch_vertices` indices make a patch. Nothing in the demo
+// is tessellated yet, the shader.rs tests draw a patch.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn draw_patches(index_count: GLsizei, patch_vertices: i32) {
+    count_draw();
+    unsafe {
+        gl::PatchParameteri(gl::PATCH_VERTICES, patch_vertices);
+        gl::DrawElements(gl::PATCHES, index_count, gl::UNSIGNED_INT, ptr::null());
+    }
+}
+
+// Runs the compute program in use, see `Shader::dispatch`.
+pub fn dispatch_compute(groups: [u32; 3]) {
+    STATE.with_borrow_mut(|state| state.stats.dispatches += 1);
+    unsafe {
+        gl::DispatchCompute(groups[0], groups[1], groups[2]);
+    }
+}
+
+// Makes writes of earlier dispatches visible to the accesses in `barriers`, e.g.
+// gl::SHADER_STORAGE_BARRIER_BIT or gl::BUFFER_UPDATE_BARRIER_BIT.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn memory_barrier(barriers: GLbitfield) {
+    unsafe {
+        gl::MemoryBarrier(barriers);
+    }
+}
+
+pub fn count_uniform() {
+    STATE.with_borrow_mut(|state| state.stats.uniforms += 1);
+}
+
+fn count_draw() {
+    STATE.with_borrow_mut(|state| state.stats.draws += 1);
+}
+
+// Starts over from GL's initial state, call after creating a context.
+pub fn reset() {
+    STATE.set(State::default());
+}
+
+// Returns the counters since the last call and resets them.
+pub fn take_gl_stats() -> GlStats {
+    STATE.with_borrow_mut(|state| std::mem::take(&mut state.stats))
+}
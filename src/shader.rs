@@ -0,0 +1,606 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::rc::Rc;
+use gl::types::{GLchar, GLenum, GLint, GLsizei, GLuint};
+use glam::{Mat3, Mat4, Vec2, Vec3, Vec4};
+use crate::components::{MAX_CASCADES, MAX_LIGHTS, MAX_SPOT_SHADOWS};
+use crate::graphics::{Graphics, BRDF_LUT_UNIT, CAMERA_BINDING, CASCADE_SHADOW_UNIT, IRRADIANCE_UNIT, LIGHTS_BINDING, PREFILTER_UNIT, SHADOWS_BINDING, SPOT_SHADOW_UNIT};
+use crate::render_state;
+use crate::shader_preprocessor::{Defines, ShaderSource};
+use crate::terrain::MAX_TERRAIN_LAYERS;
+
+// An active uniform or vertex attribute of a linked program.
+#[derive(Clone, Copy, Debug)]
+pub struct ShaderVariable {
+    pub location: i32,
+    // GL type enum, e.g. gl::FLOAT_VEC3.
+    pub kind: GLenum,
+    // Array length, 1 for non arrays.
+    pub size: i32,
+}
+
+// How a program was built and what `load_shader` found in it after linking.
+#[derive(Debug)]
+struct Reflection {
+    // Stage files and defines, used by `Shader::reload`.
+    key: ProgramKey,
+    // Stage files and included files.
+    files: Vec<String>,
+    // Stage files joined, and defines, for messages.
+    name: String,
+    // Arrays are listed under their plain name and under every `name[i]`.
+    uniforms: HashMap<String, ShaderVariable>,
+    attributes: HashMap<String, ShaderVariable>,
+    // Uniforms already reported as missing or mistyped, so each is reported once.
+    reported: RefCell<HashSet<String>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Shader {
+    pub id: u32,
+    reflection: Rc<Reflection>,
+}
+
+impl Shader {
+    // Wraps a linked program and reads its active uniforms and attributes.
+    fn new(id: u32, key: ProgramKey, files: Vec<String>) -> Self {
+        let mut uniforms = HashMap::new();
+        for (name, variable) in active_variables(id, gl::ACTIVE_UNIFORMS, gl::ACTIVE_UNIFORM_MAX_LENGTH, gl::GetActiveUniform, gl::GetUniformLocation) {
+            // GL names arrays after their first element.
+            if let Some(base) = name.strip_suffix("[0]") {
+                for i in 1..variable.size {
+                    let element = format!("{}[{}]", base, i);
+                    let location = location_of(id, &element, gl::GetUniformLocation);
+                    uniforms.insert(element, ShaderVariable { location, size: variable.size - i, ..variable });
+                }
+                uniforms.insert(base.to_string(), variable);
+            }
+            uniforms.insert(name, variable);
+        }
+        let attributes = active_variables(id, gl::ACTIVE_ATTRIBUTES, gl::ACTIVE_ATTRIBUTE_MAX_LENGTH, gl::GetActiveAttrib, gl::GetAttribLocation)
+            .into_iter()
+            .collect();
+
+        let mut name = key.0.iter().map(|(_, path)| path.as_str()).collect::<Vec<_>>().join(" + ");
+        if !key.1.is_empty() {
+            name = format!("{} [{}]", name, key.1.names().collect::<Vec<_>>().join(", "));
+        }
+        Self {
+            id,
+            reflection: Rc::new(Reflection {
+                name,
+                key,
+                files,
+                uniforms,
+                attributes,
+                reported: RefCell::default(),
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.reflection.name
+    }
+
+    // Files the program is built from, includes too.
+    pub fn sources(&self) -> impl Iterator<Item = &str> {
+        self.reflection.files.iter().map(String::as_str)
+    }
+
+    // Builds a new program from the current sources, later loads of the same permutation get
+    // it too. `self` stays valid either way.
+    pub fn reload(&self) -> Result<Shader, String> {
+        let shader = build_shader(self.reflection.key.clone())?;
+        PROGRAMS.with_borrow_mut(|programs| programs.insert(self.reflection.key.clone(), shader.clone()));
+        Ok(shader)
+    }
+
+    pub fn uniform(&self, name: &str) -> Option<ShaderVariable> {
+        self.reflection.uniforms.get(name).copied()
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<ShaderVariable> {
+        self.reflection.attributes.get(name).copied()
+    }
+
+    // Sets a uniform of the program in use, `T` has to match the GLSL type.
+    pub fn set_uniform<T: UniformValue>(&self, name: &str, value: T) {
+        self.set_uniform_array(name, &[value]);
+    }
+
+    // Sets consecutive elements of an array uniform, from `name` (`array` or `array[i]`) on.
+    pub fn set_uniform_array<T: UniformValue>(&self, name: &str, values: &[T]) {
+        let Some(uniform) = self.uniform(name) else {
+            self.report(name, "is not an active uniform (misspelled, or unused by the shader)".to_string());
+            return;
+        };
+        if !T::TYPES.contains(&uniform.kind) {
+            self.report(name, format!("is a {} and cannot be set from {}", type_name(uniform.kind), std::any::type_name::<T>()));
+            return;
+        }
+        if values.len() > uniform.size as usize {
+            self.report(name, format!("has room for {} values, got {}", uniform.size, values.len()));
+        }
+        render_state::count_uniform();
+        T::upload(uniform.location, &values[..values.len().min(uniform.size as usize)]);
+    }
+
+    pub  fn use_program(&self) {
+        render_state::use_program(self.id);
+    }
+
+    // Runs a compute program over `groups` work groups. Follow with `render_state::memory_barrier`
+    // before reading what it wrote.
+    pub fn dispatch(&self, groups: [u32; 3]) {
+        self.use_program();
+        render_state::dispatch_compute(groups);
+    }
+
+    fn report(&self, name: &str, problem: String) {
+        if self.reflection.reported.borrow_mut().insert(name.to_string()) {
+            println!("Shader {}: uniform `{}` {}", self.reflection.name, name, problem);
+        }
+    }
+}
+
+// A texture unit assigned to a sampler uniform.
+#[derive(Clone, Copy, Debug)]
+pub struct TextureUnit(pub u32);
+
+// Values `Shader::set_uniform` accepts, with the GLSL types they can be assigned to.
+pub trait UniformValue: Copy {
+    const TYPES: &'static [GLenum];
+
+    // Uploads `values` to the program in use, starting at `location`.
+    fn upload(location: i32, values: &[Self]);
+}
+
+impl UniformValue for f32 {
+    const TYPES: &'static [GLenum] = &[gl::FLOAT];
+
+    fn upload(location: i32, values: &[Self]) {
+        unsafe { gl::Uniform1fv(location, values.len() as GLsizei, values.as_ptr()) }
+    }
+}
+
+impl UniformValue for i32 {
+    const TYPES: &'static [GLenum] = &[gl::INT];
+
+    fn upload(location: i32, values: &[Self]) {
+        unsafe { gl::Uniform1iv(location, values.len() as GLsizei, values.as_ptr()) }
+    }
+}
+
+impl UniformValue for bool {
+    const TYPES: &'static [GLenum] = &[gl::BOOL];
+
+    fn upload(location: i32, values: &[Self]) {
+        let values: Vec<i32> = values.iter().map(|value| *value as i32).collect();
+        unsafe { gl::Uniform1iv(location, values.len() as GLsizei, values.as_ptr()) }
+    }
+}
+
+impl UniformValue for TextureUnit {
+    const TYPES: &'static [GLenum] = &[
+        gl::SAMPLER_2D,
+        gl::SAMPLER_3D,
+        gl::SAMPLER_CUBE,
+        gl::SAMPLER_2D_SHADOW,
+        gl::SAMPLER_2D_ARRAY,
+        gl::SAMPLER_2D_ARRAY_SHADOW,
+        gl::SAMPLER_CUBE_SHADOW,
+        gl::SAMPLER_2D_MULTISAMPLE,
+    ];
+
+    fn upload(location: i32, values: &[Self]) {
+        let units: Vec<i32> = values.iter().map(|unit| unit.0 as i32).collect();
+        unsafe { gl::Uniform1iv(location, units.len() as GLsizei, units.as_ptr()) }
+    }
+}
+
+impl UniformValue for Vec2 {
+    const TYPES: &'static [GLenum] = &[gl::FLOAT_VEC2];
+
+    fn upload(location: i32, values: &[Self]) {
+        unsafe { gl::Uniform2fv(location, values.len() as GLsizei, values.as_ptr() as *const f32) }
+    }
+}
+
+impl UniformValue for Vec3 {
+    const TYPES: &'static [GLenum] = &[gl::FLOAT_VEC3];
+
+    fn upload(location: i32, values: &[Self]) {
+        unsafe { gl::Uniform3fv(location, values.len() as GLsizei, values.as_ptr() as *const f32) }
+    }
+}
+
+impl UniformValue for Vec4 {
+    const TYPES: &'static [GLenum] = &[gl::FLOAT_VEC4];
+
+    fn upload(location: i32, values: &[Self]) {
+        unsafe { gl::Uniform4fv(location, values.len() as GLsizei, values.as_ptr() as *const f32) }
+    }
+}
+
+impl UniformValue for Mat3 {
+    const TYPES: &'static [GLenum] = &[gl::FLOAT_MAT3];
+
+    fn upload(location: i32, values: &[Self]) {
+        unsafe { gl::UniformMatrix3fv(location, values.len() as GLsizei, gl::FALSE, values.as_ptr() as *const f32) }
+    }
+}
+
+impl UniformValue for Mat4 {
+    const TYPES: &'static [GLenum] = &[gl::FLOAT_MAT4];
+
+    fn upload(location: i32, values: &[Self]) {
+        unsafe { gl::UniformMatrix4fv(location, values.len() as GLsizei, gl::FALSE, values.as_ptr() as *const f32) }
+    }
+}
+
+fn type_name(kind: GLenum) -> String {
+    let name = match kind {
+        gl::FLOAT => "float",
+        gl::FLOAT_VEC2 => "vec2",
+        gl::FLOAT_VEC3 => "vec3",
+        gl::FLOAT_VEC4 => "vec4",
+        gl::INT => "int",
+        gl::BOOL => "bool",
+        gl::FLOAT_MAT3 => "mat3",
+        gl::FLOAT_MAT4 => "mat4",
+        gl::SAMPLER_2D => "sampler2D",
+        gl::SAMPLER_CUBE => "samplerCube",
+        gl::SAMPLER_2D_ARRAY_SHADOW => "sampler2DArrayShadow",
+        _ => return format!("GL type {:#x}", kind),
+    };
+    name.to_string()
+}
+
+type GetActiveVariable = unsafe fn(GLuint, GLuint, GLsizei, *mut GLsizei, *mut GLint, *mut GLenum, *mut GLchar);
+type GetVariableLocation = unsafe fn(GLuint, *const GLchar) -> GLint;
+
+// Active uniforms or attributes of a program, by the name GL reports. Variables without a
+// location (uniform block members, built-ins like gl_VertexID) are left out.
+fn active_variables(program: u32, count: GLenum, max_length: GLenum, get_active: GetActiveVariable, get_location: GetVariableLocation) -> Vec<(String, ShaderVariable)> {
+    let mut variables = Vec::new();
+    unsafe {
+        let (mut total, mut buffer_size) = (0, 0);
+        gl::GetProgramiv(program, count, &mut total);
+        gl::GetProgramiv(program, max_length, &mut buffer_size);
+        let mut buffer = vec![0u8; buffer_size.max(1) as usize];
+        for index in 0..total as u32 {
+            let (mut length, mut size, mut kind) = (0, 0, 0);
+            get_active(program, index, buffer.len() as GLsizei, &mut length, &mut size, &mut kind, buffer.as_mut_ptr() as *mut GLchar);
+            let name = String::from_utf8_lossy(&buffer[..length as usize]).into_owned();
+            let location = location_of(program, &name, get_location);
+            if location >= 0 {
+                variables.push((name, ShaderVariable { location, kind, size }));
+            }
+        }
+    }
+    variables
+}
+
+fn location_of(program: u32, name: &str, get_location: GetVariableLocation) -> i32 {
+    let c_name = CString::new(name).unwrap();
+    unsafe { get_location(program, c_name.as_ptr()) }
+}
+
+// Stage files and defines a program is built from, see `load_shader_with`.
+type ProgramKey = (Vec<(GLenum, String)>, Defines);
+
+thread_local! {
+    // Programs built on this thread's context, every load of the same permutation shares one.
+    static PROGRAMS: RefCell<HashMap<ProgramKey, Shader>> = RefCell::default();
+}
+
+pub fn load_shader(vs_path: &str, fs_path: &str) -> Result<Shader, String> {
+    load_shader_with(vs_path, fs_path, &Defines::new())
+}
+
+// Loads the permutation of a program selected by `defines`, building it on first use.
+pub fn load_shader_with(vs_path: &str, fs_path: &str, defines: &Defines) -> Result<Shader, String> {
+    ProgramBuilder::new().vertex(vs_path).fragment(fs_path).defines(defines).build()
+}
+
+fn load_program(key: ProgramKey) -> Result<Shader, String> {
+    if let Some(shader) = PROGRAMS.with_borrow(|programs| programs.get(&key).cloned()) {
+        return Ok(shader);
+    }
+    let shader = build_shader(key.clone())?;
+    PROGRAMS.with_borrow_mut(|programs| programs.insert(key, shader.clone()));
+    Ok(shader)
+}
+
+// Stage types in pipeline order, with their names for messages.
+const STAGES: [(GLenum, &str); 6] = [
+    (gl::VERTEX_SHADER, "vertex"),
+    (gl::TESS_CONTROL_SHADER, "tessellation control"),
+    (gl::TESS_EVALUATION_SHADER, "tessellation evaluation"),
+    (gl::GEOMETRY_SHADER, "geometry"),
+    (gl::FRAGMENT_SHADER, "fragment"),
+    (gl::COMPUTE_SHADER, "compute"),
+];
+
+// Programs beyond a vertex/fragment pair: tessellation and geometry stages, or a single
+// compute stage on OpenGL 4.3+. Built programs are cached like `load_shader_with` ones, e.g.
+// `ProgramBuilder::new().vertex(..).tess_control(..).tess_evaluation(..).fragment(..).build()`.
+#[derive(Default)]
+pub struct ProgramBuilder {
+    stages: Vec<(GLenum, String)>,
+    defines: Defines,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn vertex(self, path: &str) -> Self {
+        self.stage(gl::VERTEX_SHADER, path)
+    }
+
+    // Draw tessellated programs with `render_state::draw_patches`. Only the tests below build
+    // tessellation, geometry and compute programs so far.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn tess_control(self, path: &str) -> Self {
+        self.stage(gl::TESS_CONTROL_SHADER, path)
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn tess_evaluation(self, path: &str) -> Self {
+        self.stage(gl::TESS_EVALUATION_SHADER, path)
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn geometry(self, path: &str) -> Self {
+        self.stage(gl::GEOMETRY_SHADER, path)
+    }
+
+    pub fn fragment(self, path: &str) -> Self {
+        self.stage(gl::FRAGMENT_SHADER, path)
+    }
+
+    // Run with `Shader::dispatch`, cannot be combined with other stages.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn compute(self, path: &str) -> Self {
+        self.stage(gl::COMPUTE_SHADER, path)
+    }
+
+    pub fn defines(mut self, defines: &Defines) -> Self {
+        self.defines = self.defines.with(defines);
+        self
+    }
+
+    // Setting a stage twice keeps the last path.
+    fn stage(mut self, kind: GLenum, path: &str) -> Self {
+        self.stages.retain(|(stage, _)| *stage != kind);
+        self.stages.push((kind, path.to_string()));
+        self
+    }
+
+    pub fn build(mut self) -> Result<Shader, String> {
+        let has = |kind: GLenum| self.stages.iter().any(|(stage, _)| *stage == kind);
+        if has(gl::COMPUTE_SHADER) {
+            if self.stages.len() > 1 {
+                return Err("A compute program cannot have other stages".to_string());
+            }
+            let (major, minor) = Graphics::gl_version();
+            if (major, minor) < (4, 3) {
+                return Err(format!("Compute shaders need OpenGL 4.3, the context is {}.{}", major, minor));
+            }
+        } else if !has(gl::VERTEX_SHADER) {
+            return Err("A program needs a vertex or a compute stage".to_string());
+        }
+        if has(gl::TESS_CONTROL_SHADER) && !has(gl::TESS_EVALUATION_SHADER) {
+            return Err("A tessellation control stage needs a tessellation evaluation stage".to_string());
+        }
+        // Same stages in any order are the same program.
+        self.stages.sort_by_key(|(kind, _)| STAGES.iter().position(|(stage, _)| stage == kind));
+        load_program((self.stages, self.defines))
+    }
+}
+
+// Forgets every program, call after creating a context.
+pub fn clear_shader_cache() {
+    PROGRAMS.with_borrow_mut(HashMap::clear);
+}
+
+// Limits shared with the Rust side, defined in every stage.
+fn limits() -> Defines {
+    Defines::new()
+        .set("MAX_LIGHTS", MAX_LIGHTS)
+        .set("MAX_CASCADES", MAX_CASCADES)
+        .set("MAX_SPOT_SHADOWS", MAX_SPOT_SHADOWS)
+        .set("MAX_TERRAIN_LAYERS", MAX_TERRAIN_LAYERS)
+}
+
+// Preprocesses and compiles one source file per stage and links them. Nothing is left behind on
+// failure, so `ShaderWatcher` can retry as often as sources change.
+fn build_shader(key: ProgramKey) -> Result<Shader, String> {
+    let defines = limits().with(&key.1);
+    let mut objects = Vec::new();
+    let mut files = Vec::new();
+    let mut program = Ok(0);
+    for (kind, path) in &key.0 {
+        let object = ShaderSource::load(path, &defines).and_then(|source| {
+            for file in &source.files {
+                if !files.contains(file) {
+                    files.push(file.clone());
+                }
+            }
+            compile_shader(&source.code, *kind)
+                .map_err(|log| format!("{} shader {} failed to compile:\n{}", stage_name(*kind), path, source.map_log(&log)))
+        });
+        match object {
+            Ok(object) => objects.push(object),
+            Err(e) => {
+                program = Err(e);
+                break;
+            }
+        }
+    }
+    let program = program.and_then(|_| link_program(&objects));
+    unsafe {
+        for object in objects {
+            gl::DeleteShader(object);
+        }
+    }
+    let program = program?;
+
+    bind_uniform_block(program, "Lights", LIGHTS_BINDING);
+    bind_uniform_block(program, "Shadows", SHADOWS_BINDING);
+    bind_uniform_block(program, "Camera", CAMERA_BINDING);
+
+    // Per-frame maps live on fixed units, so their samplers only need setting once.
+    let shader = Shader::new(program, key, files);
+    shader.use_program();
+    let per_frame_maps = [
+        ("irradianceMap", IRRADIANCE_UNIT),
+        ("prefilterMap", PREFILTER_UNIT),
+        ("brdfLUT", BRDF_LUT_UNIT),
+        ("cascadeShadowMap", CASCADE_SHADOW_UNIT),
+        ("spotShadowMap", SPOT_SHADOW_UNIT),
+    ];
+    for (name, unit) in per_frame_maps {
+        if shader.uniform(name).is_some() {
+            shader.set_uniform(name, TextureUnit(unit));
+        }
+    }
+    Ok(shader)
+}
+
+fn stage_name(kind: GLenum) -> &'static str {
+    STAGES.iter().find(|(stage, _)| *stage == kind).map_or("unknown", |(_, name)| name)
+}
+
+// Helper functions for loading shaders
+fn compile_shader(src: &str, ty: gl::types::GLenum) -> Result<u32, String> {
+    unsafe {
+        let shader = gl::CreateShader(ty);
+        let c_str = CString::new(src.as_bytes()).unwrap();
+        gl::ShaderSource(shader, 1, &c_str.as_ptr(), std::ptr::null());
+        gl::CompileShader(shader);
+
+        let mut success = 0;
+        gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
+        if success == 0 {
+            let mut len = 0;
+            gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
+            let mut info_log = vec![0u8; len as usize];
+            gl::GetShaderInfoLog(shader, len, std::ptr::null_mut(), info_log.as_mut_ptr() as *mut i8);
+            gl::DeleteShader(shader);
+            return Err(String::from_utf8_lossy(&info_log).to_string());
+        }
+        Ok(shader)
+    }
+}
+
+fn link_program(objects: &[u32]) -> Result<u32, String> {
+    unsafe {
+        let program = gl::CreateProgram();
+        for object in objects {
+            gl::AttachShader(program, *object);
+        }
+        gl::LinkProgram(program);
+
+        let mut success = 0;
+        gl::GetProgramiv(program, gl::LINK_STATUS, &mut success);
+        if success == 0 {
+            let mut len = 0;
+            gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
+            let mut info_log = vec![0u8; len as usize];
+            gl::GetProgramInfoLog(program, len, std::ptr::null_mut(), info_log.as_mut_ptr() as *mut i8);
+            gl::DeleteProgram(program);
+            return Err(String::from_utf8_lossy(&info_log).to_string());
+        }
+        Ok(program)
+    }
+}
+
+// Points a program's uniform block at a shared binding, programs without the block are left alone.
+fn bind_uniform_block(program: u32, name: &str, binding: u32) {
+    unsafe {
+        let c_name = CString::new(name).unwrap();
+        let index = gl::GetUniformBlockIndex(program, c_name.as_ptr());
+        if index != gl::INVALID_INDEX {
+            gl::UniformBlockBinding(program, index, binding);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_error(builder: ProgramBuilder) -> String {
+        match builder.build() {
+            Ok(_) => panic!("the program should not build"),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn compute_program_rejects_other_stages() {
+        let builder = ProgramBuilder::new().compute("tests/shaders/double.comp").vertex("assets/pbr.vert");
+        assert_eq!(build_error(builder), "A compute program cannot have other stages");
+    }
+
+    #[test]
+    fn program_needs_vertex_stage() {
+        let builder = ProgramBuilder::new().fragment("assets/pbr.frag");
+        assert_eq!(build_error(builder), "A program needs a vertex or a compute stage");
+    }
+
+    #[test]
+    fn tess_control_needs_tess_evaluation() {
+        let builder = ProgramBuilder::new().vertex("assets/pbr.vert").tess_control("terrain.tesc").fragment("assets/pbr.frag");
+        assert_eq!(build_error(builder), "A tessellation control stage needs a tessellation evaluation stage");
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "golden"), ignore = "needs a GL context, run with --features golden")]
+    fn compute_dispatch_writes_storage_buffer() {
+        let _lock = crate::golden::GL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let _graphics = Graphics::new_headless(1, 1).unwrap_or_else(|e| panic!("Failed to create a headless GL context: {}", e));
+        let shader = ProgramBuilder::new().compute("tests/shaders/double.comp").build().unwrap();
+
+        let ssbo = Graphics::create_storage_buffer(1, &[0.0f32; 4]);
+        Graphics::update_storage_buffer(ssbo, 1, &[2.0f32, 3.0]);
+        Graphics::bind_storage_buffer(0, ssbo);
+        shader.dispatch([4, 1, 1]);
+        render_state::memory_barrier(gl::BUFFER_UPDATE_BARRIER_BIT);
+        assert_eq!(Graphics::read_storage_buffer::<f32>(ssbo, 4), [0.0, 4.0, 6.0, 0.0]);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "golden"), ignore = "needs a GL context, run with --features golden")]
+    fn tessellated_program_draws_patches() {
+        let _lock = crate::golden::GL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let graphics = Graphics::new_headless(64, 64).unwrap_or_else(|e| panic!("Failed to create a headless GL context: {}", e));
+        let shader = ProgramBuilder::new()
+            .vertex("tests/shaders/patch.vert")
+            .tess_control("tests/shaders/patch.tesc")
+            .tess_evaluation("tests/shaders/patch.tese")
+            .geometry("tests/shaders/patch.geom")
+            .fragment("tests/shaders/patch.frag")
+            .build()
+            .unwrap();
+
+        // One triangle patch in clip space, covering the middle of the frame.
+        let corner = |x, y| crate::components::Vertex { position: Vec3::new(x, y, 0.0), ..Default::default() };
+        let patch = Graphics::create_mesh(vec![corner(-0.5, -0.5), corner(0.5, -0.5), corner(0.0, 0.5)], vec![0, 1, 2]);
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, graphics.framebuffer());
+        }
+        graphics.begin_frame();
+        shader.use_program();
+        render_state::bind_vertex_array(patch.vao);
+        render_state::draw_patches(3, 3);
+
+        let frame = graphics.read_frame();
+        assert_eq!(frame.get_pixel(32, 32).0, [0, 255, 0, 255]);
+        assert_ne!(frame.get_pixel(2, 2).0, [0, 255, 0, 255]);
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Folder `#include "file"` paths are relative to.
+pub const INCLUDE_DIR: &str = "assets";
+
+// `#define`s injected right after a stage's `#version` line. Each set is a separate permutation
+// of a program, see `load_shader_with`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Defines(BTreeMap<String, String>);
+
+impl Defines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `#define name value`
+    pub fn set(mut self, name: &str, value: impl ToString) -> Self {
+        self.0.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    // `#define name`, for `#ifdef name` blocks.
+    pub fn flag(self, name: &str) -> Self {
+        self.set(name, "")
+    }
+
+    // Both sets, `other` wins where they define the same name.
+    pub fn with(mut self, other: &Defines) -> Self {
+        self.0.extend(other.0.iter().map(|(name, value)| (name.clone(), value.clone())));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+}
+
+// A stage after preprocessing, remembering which file and line each of its lines came from.
+pub struct ShaderSource {
+    pub code: String,
+    // Every file read, the stage file first.
+    pub files: Vec<String>,
+    // (index in `files`, line number) of each line of `code`.
+    origins: Vec<(usize, usize)>,
+    include_dir: PathBuf,
+}
+
+impl ShaderSource {
+    // Reads the stage at `path`, expands its includes and inserts `defines`. Each file is
+    // included once per stage, later includes of it are skipped.
+    pub fn load(path: &str, defines: &Defines) -> Result<Self, String> {
+        Self::load_with_includes(path, defines, Path::new(INCLUDE_DIR))
+    }
+
+    // `load` with includes relative to `include_dir`.
+    fn load_with_includes(path: &str, defines: &Defines, include_dir: &Path) -> Result<Self, String> {
+        let mut source = ShaderSource {
+            code: String::new(),
+            files: Vec::new(),
+            origins: Vec::new(),
+            include_dir: include_dir.to_path_buf(),
+        };
+        source.append(path, Some(defines))?;
+        Ok(source)
+    }
+
+    // Rewrites the `0:line` and `0(line)` locations drivers put in compile logs to `file:line`.
+    pub fn map_log(&self, log: &str) -> String {
+        log.lines().map(|line| self.map_log_line(line)).collect::<Vec<_>>().join("\n")
+    }
+
+    // `defines` is only given for the stage file, includes must not have a `#version` line.
+    fn append(&mut self, path: &str, defines: Option<&Defines>) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read shader {}: {}", path, e))?;
+        let file = self.files.len();
+        self.files.push(path.to_string());
+
+        let has_version = text.lines().any(|line| line.trim_start().starts_with("#version"));
+        if let Some(defines) = defines.filter(|_| !has_version) {
+            self.push_defines(file, 0, defines);
+        }
+        for (i, line) in text.lines().enumerate() {
+            let number = i + 1;
+            let directive = line.trim_start();
+            if let Some(include) = directive.strip_prefix("#include") {
+                let name = include
+                    .trim()
+                    .strip_prefix('"')
+                    .and_then(|name| name.strip_suffix('"'))
+                    .ok_or_else(|| format!("{}:{}: expected #include \"file\"", path, number))?;
+                let included = self.include_dir.join(name).to_string_lossy().into_owned();
+                if !self.files.contains(&included) {
+                    self.append(&included, None).map_err(|e| format!("{}\n  included from {}:{}", e, path, number))?;
+                }
+                continue;
+            }
+            if directive.starts_with("#version") && defines.is_none() {
+                return Err(format!("{}:{}: included files cannot have a #version", path, number));
+            }
+            self.push_line(file, number, line);
+            if directive.starts_with("#version") && let Some(defines) = defines {
+                self.push_defines(file, number, defines);
+            }
+        }
+        Ok(())
+    }
+
+    fn push_defines(&mut self, file: usize, number: usize, defines: &Defines) {
+        for (name, value) in &defines.0 {
+            self.push_line(file, number, format!("#define {} {}", name, value).trim_end());
+        }
+    }
+
+    fn push_line(&mut self, file: usize, number: usize, line: &str) {
+        self.code.push_str(line);
+        self.code.push('\n');
+        self.origins.push((file, number));
+    }
+
+    fn map_log_line(&self, line: &str) -> String {
+        // Mesa writes `0:12(5): error`, NVIDIA `0(12) : error`, AMD and Apple `ERROR: 0:12: ...`.
+        for (start, _) in line.match_indices('0') {
+            if start > 0 && !line[..start].ends_with(char::is_whitespace) {
+                continue;
+            }
+            let rest = &line[start + 1..];
+            let parenthesized = rest.starts_with('(');
+            if !parenthesized && !rest.starts_with(':') {
+                continue;
+            }
+            let digits = rest[1..].chars().take_while(char::is_ascii_digit).count();
+            if digits == 0 || (parenthesized && !rest[1 + digits..].starts_with(')')) {
+                continue;
+            }
+            let Some(&(file, number)) = rest[1..1 + digits].parse::<usize>().ok().and_then(|n| self.origins.get(n.wrapping_sub(1))) else {
+                continue;
+            };
+            let end = start + 2 + digits + parenthesized as usize;
+            return format!("{}{}:{}{}", &line[..start], self.files[file], number, &line[end..]);
+        }
+        line.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fixture stages and includes, see tests/shaders.
+    const FIXTURES: &str = "tests/shaders";
+
+    fn load(name: &str, defines: &Defines) -> Result<ShaderSource, String> {
+        ShaderSource::load_with_includes(&format!("{}/{}", FIXTURES, name), defines, Path::new(FIXTURES))
+    }
+
+    // main.frag with its includes, two defines after its `#version`:
+    //  1-3 main.frag:1, 4-6 common.glsl:1-3, 7-9 lighting.glsl:2-4, 10-13 main.frag:4-7
+    fn main_frag() -> ShaderSource {
+        load("main.frag", &Defines::new().set("LIGHTS", 4).flag("SHADOWS")).unwrap()
+    }
+
+    #[test]
+    fn expands_includes_once() {
+        let source = main_frag();
+        let expected = [
+            "#version 330 core",
+            "#define LIGHTS 4",
+            "#define SHADOWS",
+            "vec3 halve(vec3 v) {",
+            "    return v * 0.5;",
+            "}",
+            "vec3 light(vec3 v) {",
+            "    return halve(v);",
+            "}",
+            "out vec4 color;",
+            "void main() {",
+            "    color = vec4(light(halve(vec3(1.0))), 1.0);",
+            "}",
+        ];
+        assert_eq!(source.code.lines().collect::<Vec<_>>(), expected);
+        assert_eq!(source.files, ["tests/shaders/main.frag", "tests/shaders/common.glsl", "tests/shaders/lighting.glsl"]);
+    }
+
+    #[test]
+    fn injects_defines_first_without_version() {
+        let source = load("unversioned.frag", &Defines::new().flag("SHADOWS")).unwrap();
+        assert_eq!(source.code, "#define SHADOWS\nvoid main() {}\n");
+    }
+
+    #[test]
+    fn rejects_version_in_include() {
+        let error = load("versioned.frag", &Defines::new()).err().unwrap();
+        assert_eq!(
+            error,
+            "tests/shaders/versioned.glsl:1: included files cannot have a #version\n  included from tests/shaders/versioned.frag:2"
+        );
+    }
+
+    #[test]
+    fn maps_mesa_log() {
+        assert_eq!(
+            main_frag().map_log("0:5(12): error: `y' undeclared"),
+            "tests/shaders/common.glsl:2(12): error: `y' undeclared"
+        );
+    }
+
+    #[test]
+    fn maps_nvidia_log() {
+        assert_eq!(
+            main_frag().map_log("0(8) : error C1008: undefined variable \"y\""),
+            "tests/shaders/lighting.glsl:3 : error C1008: undefined variable \"y\""
+        );
+    }
+
+    #[test]
+    fn maps_amd_and_apple_log() {
+        assert_eq!(
+            main_frag().map_log("ERROR: 0:11: 'color' : syntax error"),
+            "ERROR: tests/shaders/main.frag:5: 'color' : syntax error"
+        );
+    }
+
+    #[test]
+    fn keeps_lines_without_location() {
+        let log = "warning: unused variable\n0:99(1): error: past the end";
+        assert_eq!(main_frag().map_log(log), log);
+    }
+}
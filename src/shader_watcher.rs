@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+use flecs_ecs::prelude::*;
+use crate::components::{EmissiveShader, PBRShader};
+use crate::ecs::Ecs;
+use crate::render_state;
+use crate::shader::Shader;
+
+// Source files are checked at most this often.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Rebuilds the programs of `PBRShader` and `EmissiveShader` components when their source files
+// change on disk, swaps the new program into every component using the old one and deletes the
+// old one. A program that fails to build prints its log and the old one stays in use until the
+// next edit.
+pub struct ShaderWatcher {
+    pbr_shaders: Query<&'static mut PBRShader>,
+    emissive_shaders: Query<&'static mut EmissiveShader>,
+    // Modification time of each (program, source file) when it was built or last checked.
+    seen: HashMap<(u32, String), SystemTime>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new(world: &Ecs) -> Self {
+        Self {
+            pbr_shaders: world.world.new_query::<&mut PBRShader>(),
+            emissive_shaders: world.world.new_query::<&mut EmissiveShader>(),
+            seen: HashMap::new(),
+            last_poll: Instant::now(),
+        }
+    }
+
+    // Call once per frame. `replace` gets each replaced program id with its new shader, so shaders
+    // held outside components (e.g. by `TerrainStreamer`) can be swapped before the old program
+    // is deleted.
+    pub fn update(&mut self, mut replace: impl FnMut(u32, &Shader)) {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return;
+        }
+        self.last_poll = Instant::now();
+
+        let mut shaders: Vec<Shader> = Vec::new();
+        let mut collect = |shader: &Shader| {
+            if !shaders.iter().any(|known| known.id == shader.id) {
+                shaders.push(shader.clone());
+            }
+        };
+        self.pbr_shaders.each(|shader| collect(&shader.0));
+        self.emissive_shaders.each(|shader| collect(&shader.0));
+
+        let mut reloaded = Vec::new();
+        for shader in shaders {
+            if !self.changed(&shader) {
+                continue;
+            }
+            match shader.reload() {
+                Ok(new_shader) => {
+                    println!("Reloaded shader {}", shader.name());
+                    self.changed(&new_shader);
+                    reloaded.push((shader.id, new_shader));
+                }
+                Err(log) => println!("Failed to reload shader {}: {}", shader.name(), log),
+            }
+        }
+        if reloaded.is_empty() {
+            return;
+        }
+
+        let replacement = |shader: &Shader| reloaded.iter().find(|(old, _)| *old == shader.id).map(|(_, new_shader)| new_shader.clone());
+        self.pbr_shaders.each(|shader| {
+            if let Some(new_shader) = replacement(&shader.0) {
+                shader.0 = new_shader;
+            }
+        });
+        self.emissive_shaders.each(|shader| {
+            if let Some(new_shader) = replacement(&shader.0) {
+                shader.0 = new_shader;
+            }
+        });
+        // Instance batches match shaders by id, they start new batches and drop the old ones.
+        for (old, new_shader) in &reloaded {
+            replace(*old, new_shader);
+            render_state::delete_program(*old);
+            self.seen.retain(|(program, _), _| program != old);
+        }
+    }
+
+    // Records the modification times of the shader's sources and returns whether any changed
+    // since they were last recorded. Sources seen for the first time count as unchanged.
+    fn changed(&mut self, shader: &Shader) -> bool {
+        let mut changed = false;
+        for path in shader.sources() {
+            let Ok(modified) = fs::metadata(path).and_then(|metadata| metadata.modified()) else {
+                continue;
+            };
+            if let Some(previous) = self.seen.insert((shader.id, path.to_string()), modified) {
+                changed |= previous != modified;
+            }
+        }
+        changed
+    }
+}
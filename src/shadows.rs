@@ -0,0 +1,219 @@
+use std::ptr;
+use flecs_ecs::prelude::*;
+use gl::types::GLsizei;
+use glam::{Mat4, Vec3, Vec4};
+use crate::components::*;
+use crate::ecs::Ecs;
+use crate::graphics::{self, Graphics};
+use crate::shader::{load_shader, Shader};
+use crate::render_state;
+
+// std140 layout of the `Shadows` uniform block in the shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShadowBlock {
+    pub cascade_matrices: [Mat4; MAX_CASCADES],
+    // Far view depth of each cascade.
+    pub cascade_splits: [f32; MAX_CASCADES],
+    pub spot_matrices: [Mat4; MAX_SPOT_SHADOWS],
+    // xyz = camera forward, w = cascade count.
+    pub camera_forward: [f32; 4],
+}
+
+// Renders depth maps for the shadow slots the Light System assigned: cascades for the sun
+// and one layer per shadowed spot light. Run it after the Light System and before the scene.
+pub struct ShadowRenderer {
+    resolution: u32,
+    cascade_count: usize,
+    // Blend between uniform (0) and logarithmic (1) cascade splits.
+    pub split_lambda: f32,
+    // Cascades cover the view frustum up to this distance at most.
+    pub max_distance: f32,
+    cascade_map: u32,
+    spot_map: u32,
+    fbo: u32,
+    ubo: u32,
+    shader: Shader,
+    casters: Query<(&'static Mesh, &'static (Transform, Global))>,
+}
+
+impl ShadowRenderer {
+    pub fn new(world: &Ecs, resolution: u32, cascade_count: usize) -> Result<Self, String> {
+        let shader = load_shader("assets/shadow.vert", "assets/shadow.frag")?;
+        let cascade_map = create_depth_array(resolution, MAX_CASCADES);
+        let spot_map = create_depth_array(resolution, MAX_SPOT_SHADOWS);
+
+        let mut fbo = 0;
+        unsafe {
+            gl::GenFramebuffers(1, &mut fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+            gl::DrawBuffer(gl::NONE);
+            gl::ReadBuffer(gl::NONE);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+
+        Ok(Self {
+            resolution,
+            cascade_count: cascade_count.clamp(1, MAX_CASCADES),
+            split_lambda: 0.75,
+            max_distance: 100.0,
+            cascade_map,
+            spot_map,
+            fbo,
+            ubo: Graphics::create_uniform_buffer::<ShadowBlock>(graphics::SHADOWS_BINDING),
+            shader,
+            casters: world.world.new_query::<(&Mesh, &(Transform, Global))>(),
+        })
+    }
+
+    pub fn render(&self, world: &Ecs) {
+        let (camera_view, camera_projection) = world.world.map::<&ActiveCameraData, _>(|camera| (camera.view, camera.projection));
+        let lights = world.world.map::<&ActiveLightData, _>(|lights| lights.block);
+
+        let mut block = ShadowBlock::default();
+        let camera_forward = camera_view.inverse().transform_vector3(Vec3::NEG_Z).normalize();
+        block.camera_forward = [camera_forward.x, camera_forward.y, camera_forward.z, 0.0];
+
+        for light in &lights.lights[..lights.count as usize] {
+            let slot = light.shadow[0];
+            if slot < 0.0 {
+                continue;
+            }
+            let position = Vec3::new(light.position[0], light.position[1], light.position[2]);
+            let direction = Vec3::new(light.direction[0], light.direction[1], light.direction[2]);
+
+            if light.position[3] == 0.0 {
+                let (matrices, splits) = self.cascades(camera_view, camera_projection, direction);
+                for (i, matrix) in matrices.iter().enumerate().take(self.cascade_count) {
+                    block.cascade_matrices[i] = *matrix;
+                    block.cascade_splits[i] = splits[i];
+                    self.render_layer(self.cascade_map, i, matrix);
+                }
+                block.camera_forward[3] = self.cascade_count as f32;
+            } else {
+                let slot = slot as usize;
+                let outer_angle = light.cone[1].acos();
+                let range = light.direction[3];
+                let projection = Mat4::perspective_rh_gl((2.0 * outer_angle).min(3.0), 1.0, 0.1, range.max(0.2));
+                let view = Mat4::look_at_rh(position, position + direction, up_for(direction));
+                block.spot_matrices[slot] = projection * view;
+                self.render_layer(self.spot_map, slot, &block.spot_matrices[slot]);
+            }
+        }
+
+        Graphics::update_uniform_buffer(self.ubo, &block);
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        render_state::bind_texture(graphics::CASCADE_SHADOW_UNIT, gl::TEXTURE_2D_ARRAY, self.cascade_map);
+        render_state::bind_texture(graphics::SPOT_SHADOW_UNIT, gl::TEXTURE_2D_ARRAY, self.spot_map);
+    }
+
+    // Light-space matrices and far view depths of each cascade. Every cascade is fitted to the
+    // bounding sphere of its frustum slice and snapped to whole texels, so shadows do not
+    // shimmer when the camera turns or moves.
+    fn cascades(&self, view: Mat4, projection: Mat4, direction: Vec3) -> ([Mat4; MAX_CASCADES], [f32; MAX_CASCADES]) {
+        // Recover the clip planes of a perspective_rh_gl projection.
+        let m22 = projection.z_axis.z;
+        let m32 = projection.w_axis.z;
+        let near = m32 / (m22 - 1.0);
+        let far = (m32 / (m22 + 1.0)).min(self.max_distance);
+
+        let count = self.cascade_count as f32;
+        let mut splits = [0.0; MAX_CASCADES];
+        for (i, split) in splits.iter_mut().enumerate().take(self.cascade_count) {
+            let p = (i + 1) as f32 / count;
+            let log = near * (far / near).powf(p);
+            let uniform = near + (far - near) * p;
+            *split = self.split_lambda * log + (1.0 - self.split_lambda) * uniform;
+        }
+
+        let inverse_view_projection = (projection * view).inverse();
+        let ndc_depth = |depth: f32| {
+            let clip = projection * Vec4::new(0.0, 0.0, -depth, 1.0);
+            clip.z / clip.w
+        };
+
+        let mut matrices = [Mat4::IDENTITY; MAX_CASCADES];
+        let mut slice_near = near;
+        for i in 0..self.cascade_count {
+            let (z0, z1) = (ndc_depth(slice_near), ndc_depth(splits[i]));
+            let mut corners = [Vec3::ZERO; 8];
+            for (c, corner) in corners.iter_mut().enumerate() {
+                let ndc = Vec3::new(
+                    if c & 1 == 0 { -1.0 } else { 1.0 },
+                    if c & 2 == 0 { -1.0 } else { 1.0 },
+                    if c & 4 == 0 { z0 } else { z1 },
+                );
+                *corner = inverse_view_projection.project_point3(ndc);
+            }
+            let center = corners.iter().copied().sum::<Vec3>() / 8.0;
+            let radius = corners.iter().map(|c| c.distance(center)).fold(0.0, f32::max);
+            let radius = (radius * 16.0).ceil() / 16.0;
+
+            // Pull the eye far back so casters outside the slice still land in the map.
+            let back = radius + self.max_distance;
+            let light_view = Mat4::look_at_rh(center - direction * back, center, up_for(direction));
+            let light_projection = Mat4::orthographic_rh_gl(-radius, radius, -radius, radius, 0.0, back + radius);
+            let mut matrix = light_projection * light_view;
+
+            let texels = self.resolution as f32 / 2.0;
+            let origin = matrix.project_point3(Vec3::ZERO) * texels;
+            let offset = (origin.round() - origin) / texels;
+            matrix = Mat4::from_translation(Vec3::new(offset.x, offset.y, 0.0)) * matrix;
+
+            matrices[i] = matrix;
+            slice_near = splits[i];
+        }
+        (matrices, splits)
+    }
+
+    fn render_layer(&self, map: u32, layer: usize, light_space: &Mat4) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::FramebufferTextureLayer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, map, 0, layer as i32);
+            gl::Viewport(0, 0, self.resolution as i32, self.resolution as i32);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+            gl::Enable(gl::DEPTH_TEST);
+        }
+        self.shader.use_program();
+        self.shader.set_uniform("lightSpace", *light_space);
+        self.casters.each(|(mesh, world)| {
+            self.shader.set_uniform("model", world.0);
+            render_state::bind_vertex_array(mesh.vao);
+            render_state::draw_elements(mesh.indices.len() as GLsizei);
+        });
+    }
+}
+
+fn up_for(direction: Vec3) -> Vec3 {
+    if direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y }
+}
+
+fn create_depth_array(resolution: u32, layers: usize) -> u32 {
+    let mut id = 0;
+    unsafe {
+        gl::GenTextures(1, &mut id);
+        render_state::bind_texture(0, gl::TEXTURE_2D_ARRAY, id);
+        gl::TexImage3D(
+            gl::TEXTURE_2D_ARRAY,
+            0,
+            gl::DEPTH_COMPONENT32F as i32,
+            resolution as i32,
+            resolution as i32,
+            layers as i32,
+            0,
+            gl::DEPTH_COMPONENT,
+            gl::FLOAT,
+            ptr::null(),
+        );
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as i32);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as i32);
+        gl::TexParameterfv(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_BORDER_COLOR, [1.0f32; 4].as_ptr());
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as i32);
+    }
+    id
+}
@@ -0,0 +1,920 @@
+use std::collections::HashMap;
+use flecs_ecs::prelude::Entity;
+use glam::{vec2, vec3, Mat4, Vec3, Vec4};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin, RidgedMulti, Seedable, Simplex, Worley};
+use crate::components::{Global, Mesh, Terrain, TerrainMaterial, Transform, Vertex};
+use crate::ecs::Ecs;
+use crate::erosion::{erode, ErosionSettings};
+use crate::graphics::Graphics;
+use crate::shader::{Shader, TextureUnit};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+    Worley,
+    RidgedMulti,
+}
+
+// Everything that shapes a generated world. The same settings always produce the same terrain.
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainSettings {
+    pub seed: u32,
+    pub noise: NoiseKind,
+    // Frequency of the base octave, smaller values zoom out to larger features.
+    pub scale: f32,
+    // Multiplier taking the normalized [0, 1] noise to world units.
+    pub height: f32,
+    pub octaves: usize,
+    pub lacunarity: f32,
+    pub persistence: f32,
+    // Displacement of the sample position by a second noise field, in world units (0 disables it).
+    pub warp_strength: f32,
+    pub warp_scale: f32,
+    // Optional erosion pass run on the heights before normals are computed.
+    pub erosion: Option<ErosionSettings>,
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            noise: NoiseKind::Perlin,
+            scale: 0.02,
+            height: 10.0,
+            octaves: 6,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            warp_strength: 0.0,
+            warp_scale: 0.01,
+            erosion: None,
+        }
+    }
+}
+
+// Samples the terrain height in world space, so neighbouring chunks agree on shared edges.
+pub struct TerrainGenerator {
+    settings: TerrainSettings,
+    noise: Box<dyn NoiseFn<f64, 2>>,
+    warp: [Perlin; 2],
+}
+
+impl TerrainGenerator {
+    pub fn new(settings: TerrainSettings) -> Self {
+        let noise: Box<dyn NoiseFn<f64, 2>> = match settings.noise {
+            NoiseKind::Perlin => Box::new(fractal::<Fbm<Perlin>>(&settings)),
+            NoiseKind::Simplex => Box::new(fractal::<Fbm<Simplex>>(&settings)),
+            NoiseKind::Worley => Box::new(fractal::<Fbm<Worley>>(&settings)),
+            NoiseKind::RidgedMulti => Box::new(fractal::<RidgedMulti<Perlin>>(&settings)),
+        };
+        Self {
+            settings,
+            noise,
+            warp: [
+                Perlin::new(settings.seed.wrapping_add(1)),
+                Perlin::new(settings.seed.wrapping_add(2)),
+            ],
+        }
+    }
+
+    pub fn height(&self, x: f32, z: f32) -> f32 {
+        let (mut x, mut z) = (x as f64, z as f64);
+        if self.settings.warp_strength != 0.0 {
+            let warp_point = [x * self.settings.warp_scale as f64, z * self.settings.warp_scale as f64];
+            let strength = self.settings.warp_strength as f64;
+            x += self.warp[0].get(warp_point) * strength;
+            z += self.warp[1].get(warp_point) * strength;
+        }
+
+        let scale = self.settings.scale as f64;
+        let noise_value = self.noise.get([x * scale, z * scale]) as f32;
+        (noise_value + 1.0) / 2.0 * self.settings.height
+    }
+
+    // Central differences over one grid step. Sampling the generator instead of the mesh
+    // gives border vertices the same normal as their twin in the neighbouring chunk.
+    pub fn normal(&self, x: f32, z: f32) -> Vec3 {
+        let left = self.height(x - 1.0, z);
+        let right = self.height(x + 1.0, z);
+        let down = self.height(x, z - 1.0);
+        let up = self.height(x, z + 1.0);
+        vec3(left - right, 2.0, down - up).normalize()
+    }
+}
+
+// Screen-space error budget for picking a chunk's level of detail.
+pub struct TerrainLod {
+    // Largest tolerated on-screen deviation from the full-resolution surface, in pixels.
+    pub max_pixel_error: f32,
+    // viewport_height / (2 * tan(fov_y / 2)), converts a world-space error at distance 1 into pixels.
+    pub pixel_scale: f32,
+}
+
+impl TerrainLod {
+    pub fn new(max_pixel_error: f32, viewport_height: u32, fov_y_deg: f32) -> Self {
+        Self {
+            max_pixel_error,
+            pixel_scale: viewport_height as f32 / (2.0 * (fov_y_deg.to_radians() / 2.0).tan()),
+        }
+    }
+
+    // Picks the coarsest level whose geometric error projects below the pixel budget.
+    fn select(&self, errors: &[f32], distance: f32) -> usize {
+        let distance = distance.max(1.0);
+        errors
+            .iter()
+            .rposition(|error| error * self.pixel_scale / distance <= self.max_pixel_error)
+            .unwrap_or(0)
+    }
+}
+
+pub const MAX_TERRAIN_LAYERS: usize = 4;
+
+impl TerrainMaterial {
+    // Binds the layer textures to units 0..n and the splat map after them.
+    pub fn bind(&self, shader: &Shader) {
+        let layers = &self.layers[..self.layers.len().min(MAX_TERRAIN_LAYERS)];
+        shader.set_uniform("layerCount", layers.len() as i32);
+        for (i, layer) in layers.iter().enumerate() {
+            Graphics::bind_texture(i as u32, &layer.texture);
+        }
+        shader.set_uniform_array("layerTextures", &(0..layers.len() as u32).map(TextureUnit).collect::<Vec<_>>());
+        shader.set_uniform_array("layerTiling", &layers.iter().map(|layer| layer.tiling).collect::<Vec<_>>());
+        shader.set_uniform_array("layerHeight", &layers.iter().map(|layer| layer.height).collect::<Vec<_>>());
+        shader.set_uniform_array("layerSlope", &layers.iter().map(|layer| layer.slope).collect::<Vec<_>>());
+
+        let splat_unit = MAX_TERRAIN_LAYERS as u32;
+        shader.set_uniform("splatMap", TextureUnit(splat_unit));
+        match &self.splat_map {
+            Some(splat_map) => {
+                Graphics::bind_texture(splat_unit, &splat_map.texture);
+                shader.set_uniform("useSplatMap", true);
+                shader.set_uniform("splatRect", Vec4::new(splat_map.origin.x, splat_map.origin.y, splat_map.size.x, splat_map.size.y));
+            }
+            None => shader.set_uniform("useSplatMap", false),
+        }
+    }
+}
+
+// A grid of heights with one sample per world unit, the CPU side of every terrain mesh.
+#[derive(Clone, Debug)]
+pub struct Heightfield {
+    pub width: u32,
+    pub depth: u32,
+    pub heights: Vec<f32>,
+}
+
+impl Heightfield {
+    // Samples `width` x `depth` heights starting at world position (origin_x, origin_z).
+    pub fn generate(generator: &TerrainGenerator, origin_x: f32, origin_z: f32, width: u32, depth: u32) -> Self {
+        let mut heights = Vec::with_capacity((width * depth) as usize);
+        for z in 0..depth {
+            for x in 0..width {
+                heights.push(generator.height(origin_x + x as f32, origin_z + z as f32));
+            }
+        }
+        Self { width, depth, heights }
+    }
+
+    // Loads a grayscale PNG (8 or 16 bit), mapping black to 0 and white to `max_height`.
+    pub fn load_png(path: &str, max_height: f32) -> Result<Self, String> {
+        let img = image::open(path)
+            .map_err(|e| format!("Failed to load heightmap {}: {}", path, e))?
+            .into_luma16();
+        let (width, depth) = img.dimensions();
+        if width < 2 || depth < 2 {
+            return Err(format!("Heightmap {} must be at least 2x2 pixels", path));
+        }
+        let heights = img
+            .into_raw()
+            .into_iter()
+            .map(|v| v as f32 / u16::MAX as f32 * max_height)
+            .collect();
+        Ok(Self { width, depth, heights })
+    }
+
+    // Writes a 16-bit grayscale PNG, the inverse of `load_png` for the same `max_height`.
+    // Heights outside [0, max_height] are clamped.
+    pub fn save_png(&self, path: &str, max_height: f32) -> Result<(), String> {
+        let data: Vec<u16> = self
+            .heights
+            .iter()
+            .map(|h| ((h / max_height).clamp(0.0, 1.0) * u16::MAX as f32).round() as u16)
+            .collect();
+        let img = image::ImageBuffer::<image::Luma<u16>, Vec<u16>>::from_raw(self.width, self.depth, data)
+            .ok_or_else(|| "Heightfield size does not match its sample count".to_string())?;
+        img.save(path).map_err(|e| format!("Failed to save heightmap {}: {}", path, e))
+    }
+
+    pub fn height(&self, x: u32, z: u32) -> f32 {
+        self.heights[(z * self.width + x) as usize]
+    }
+
+    // Central differences inside the grid, one-sided differences along its border.
+    pub fn normal(&self, x: u32, z: u32) -> Vec3 {
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.width - 1));
+        let (z0, z1) = (z.saturating_sub(1), (z + 1).min(self.depth - 1));
+        let dx = (self.height(x1, z) - self.height(x0, z)) / (x1 - x0) as f32;
+        let dz = (self.height(x, z1) - self.height(x, z0)) / (z1 - z0) as f32;
+        vec3(-dx, 1.0, -dz).normalize()
+    }
+
+    // One vertex per sample with UVs spanning the grid, the layout of a streamed chunk before
+    // its border normals and skirt.
+    pub fn vertices(&self) -> Vec<Vertex> {
+        let mut vertices = Vec::with_capacity((self.width * self.depth) as usize);
+        for z in 0..self.depth {
+            for x in 0..self.width {
+                vertices.push(Vertex {
+                    position: vec3(x as f32, self.height(x, z), z as f32),
+                    normal: self.normal(x, z),
+                    uv: vec2(x as f32 / (self.width - 1) as f32, z as f32 / (self.depth - 1) as f32),
+                });
+            }
+        }
+        vertices
+    }
+
+    // Turns a heightfield (generated or loaded with `load_png`) into a terrain mesh, each quad
+    // split along the same diagonal as the chunks.
+    pub fn create_mesh(&self) -> Mesh {
+        let mut indices = Vec::with_capacity(((self.width - 1) * (self.depth - 1) * 6) as usize);
+        for z in 0..self.depth - 1 {
+            for x in 0..self.width - 1 {
+                let top_left = z * self.width + x;
+                let top_right = top_left + 1;
+                let bottom_left = top_left + self.width;
+                let bottom_right = bottom_left + 1;
+
+                indices.extend_from_slice(&[top_left, bottom_left, top_right]);
+                indices.extend_from_slice(&[top_right, bottom_left, bottom_right]);
+            }
+        }
+        Graphics::create_mesh(self.vertices(), indices)
+    }
+}
+
+fn fractal<T: MultiFractal + Seedable + Default>(settings: &TerrainSettings) -> T {
+    T::default()
+        .set_seed(settings.seed)
+        .set_octaves(settings.octaves)
+        .set_lacunarity(settings.lacunarity as f64)
+        .set_persistence(settings.persistence as f64)
+}
+
+// Extra samples generated around each chunk so droplets can flow in from outside it.
+const EROSION_PADDING: u32 = 16;
+
+struct TerrainChunk {
+    entity: Entity,
+    center: Vec3,
+    // Index lists per level of detail, level `n` samples every 2^n-th vertex.
+    lod_indices: Vec<Vec<u32>>,
+    // Maximum height deviation of each level from the full-resolution grid.
+    lod_errors: Vec<f32>,
+    lod: usize,
+}
+
+// A heightfield placed with `TerrainStreamer::add_heightfield`, covering chunks first..=last.
+struct ImportedTerrain {
+    entity: Entity,
+    first: (i32, i32),
+    last: (i32, i32),
+}
+
+impl ImportedTerrain {
+    fn covers(&self, (x, z): (i32, i32)) -> bool {
+        (self.first.0..=self.last.0).contains(&x) && (self.first.1..=self.last.1).contains(&z)
+    }
+}
+
+// Splits the world into square chunks keyed by grid coordinate and keeps the ones
+// within `view_distance` chunks of the camera alive.
+//
+// Chunks are geomipmapped: every level of detail reuses the full-resolution vertex buffer
+// with a sparser index list. Each chunk carries a skirt hanging down from its border so the
+// T-junctions between neighbours at different levels never show as cracks.
+//
+// Hand-authored heightfields (e.g. from `Heightfield::load_png`) replace the chunks they overlap.
+pub struct TerrainStreamer {
+    generator: TerrainGenerator,
+    chunk_size: u32,
+    view_distance: i32,
+    chunks_per_frame: usize,
+    lod: TerrainLod,
+    shader: Shader,
+    material: TerrainMaterial,
+    chunks: HashMap<(i32, i32), TerrainChunk>,
+    imported: Vec<ImportedTerrain>,
+}
+
+impl TerrainStreamer {
+    // `chunk_size` must be a power of two so every level of detail divides it evenly.
+    pub fn new(generator: TerrainGenerator, chunk_size: u32, view_distance: i32, lod: TerrainLod, shader: Shader, material: TerrainMaterial) -> Self {
+        assert!(chunk_size.is_power_of_two(), "terrain chunk size must be a power of two");
+        Self {
+            generator,
+            chunk_size,
+            view_distance,
+            chunks_per_frame: 2,
+            lod,
+            shader,
+            material,
+            chunks: HashMap::new(),
+            imported: Vec::new(),
+        }
+    }
+
+    // Streams new chunks with `shader` from now on if it replaces the current one.
+    pub fn replace_shader(&mut self, old: u32, shader: &Shader) {
+        if self.shader.id == old {
+            self.shader = shader.clone();
+        }
+    }
+
+    pub fn chunk_coord(&self, x: f32, z: f32) -> (i32, i32) {
+        let size = self.chunk_size as f32;
+        ((x / size).floor() as i32, (z / size).floor() as i32)
+    }
+
+    // Generates missing chunks around `camera_pos` (nearest first, a few per frame to avoid hitches),
+    // frees the ones that fell out of range and refreshes the level of detail of the rest.
+    pub fn update(&mut self, world: &Ecs, camera_pos: Vec3) {
+        let (cx, cz) = self.chunk_coord(camera_pos.x, camera_pos.z);
+
+        // Unload with one chunk of hysteresis so walking along a border does not thrash.
+        let unload_distance = self.view_distance + 1;
+        let far: Vec<(i32, i32)> = self
+            .chunks
+            .keys()
+            .filter(|(x, z)| (x - cx).pow(2) + (z - cz).pow(2) > unload_distance.pow(2))
+            .copied()
+            .collect();
+        for coord in far {
+            if let Some(chunk) = self.chunks.remove(&coord) {
+                world.destroy_entity(chunk.entity);
+            }
+        }
+
+        let mut missing = Vec::new();
+        for z in cz - self.view_distance..=cz + self.view_distance {
+            for x in cx - self.view_distance..=cx + self.view_distance {
+                let distance = (x - cx).pow(2) + (z - cz).pow(2);
+                let covered = self.imported.iter().any(|imported| imported.covers((x, z)));
+                if distance <= self.view_distance.pow(2) && !covered && !self.chunks.contains_key(&(x, z)) {
+                    missing.push((distance, (x, z)));
+                }
+            }
+        }
+        missing.sort();
+
+        for (_, coord) in missing.into_iter().take(self.chunks_per_frame) {
+            let chunk = self.create_chunk(world, coord);
+            self.chunks.insert(coord, chunk);
+        }
+
+        for chunk in self.chunks.values_mut() {
+            let lod = self.lod.select(&chunk.lod_errors, chunk.center.distance(camera_pos));
+            if lod != chunk.lod {
+                chunk.lod = lod;
+                let indices = chunk.lod_indices[lod].clone();
+                chunk.entity.entity_view(&world.world).get::<&mut Mesh>(|mesh| {
+                    Graphics::update_mesh_indices(mesh, indices);
+                });
+            }
+        }
+    }
+
+    fn create_chunk(&self, world: &Ecs, (cx, cz): (i32, i32)) -> TerrainChunk {
+        let size = self.chunk_size;
+        let side = size + 1;
+        let origin_x = cx as f32 * size as f32;
+        let origin_z = cz as f32 * size as f32;
+
+        let heightfield = self.chunk_heightfield((cx, cz), origin_x, origin_z);
+        let heights = &heightfield.heights;
+
+        let mut vertices = heightfield.vertices();
+        // Border normals come from the generator so they match the neighbouring chunk.
+        for edge in 0..4 {
+            for i in 0..side {
+                let vertex = &mut vertices[border_index(edge, i, side) as usize];
+                vertex.normal = self.generator.normal(origin_x + vertex.position.x, origin_z + vertex.position.z);
+            }
+        }
+
+        let levels = size.trailing_zeros() as usize + 1;
+        let lod_errors: Vec<f32> = (0..levels).map(|level| lod_error(heights, size, 1 << level)).collect();
+
+        // Skirt vertices: a lowered copy of every border vertex, deep enough to cover the
+        // largest gap the coarsest level can open against a full-resolution neighbour.
+        let skirt_depth = lod_errors.iter().copied().fold(0.0, f32::max) + 1.0;
+        let skirt_start = vertices.len() as u32;
+        for edge in 0..4 {
+            for i in 0..side {
+                let mut vertex = vertices[border_index(edge, i, side) as usize];
+                vertex.position.y -= skirt_depth;
+                vertices.push(vertex);
+            }
+        }
+
+        let lod_indices: Vec<Vec<u32>> = (0..levels)
+            .map(|level| lod_indices(size, 1 << level, skirt_start))
+            .collect();
+
+        let (min_height, max_height) = heights
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), &h| (min.min(h), max.max(h)));
+        let center = vec3(
+            origin_x + size as f32 / 2.0,
+            (min_height + max_height) / 2.0,
+            origin_z + size as f32 / 2.0,
+        );
+
+        let name = format!("terrain_chunk_{}_{}", cx, cz);
+        let entity = world.create_entity(&name, vec3(origin_x, 0.0, origin_z), Vec3::ONE, Vec3::ZERO, None);
+        world.add_pbr_shader(entity, self.shader.clone());
+        world.add_mesh(entity, Graphics::create_mesh(vertices, lod_indices[0].clone()), None);
+        world.add_terrain_material(entity, self.material.clone());
+
+        world.add_terrain(entity, Terrain { heightfield });
+
+        TerrainChunk {
+            entity,
+            center,
+            lod_indices,
+            lod_errors,
+            lod: 0,
+        }
+    }
+
+    // Erosion needs context beyond the chunk, so it runs on a padded grid. The erosion delta
+    // fades out towards the chunk border, which stays on the raw noise shared with the neighbour.
+    fn chunk_heightfield(&self, (cx, cz): (i32, i32), origin_x: f32, origin_z: f32) -> Heightfield {
+        let side = self.chunk_size + 1;
+        let settings = &self.generator.settings;
+        let Some(erosion) = settings.erosion else {
+            return Heightfield::generate(&self.generator, origin_x, origin_z, side, side);
+        };
+
+        let pad = EROSION_PADDING;
+        let padded_side = side + 2 * pad;
+        let mut padded = Heightfield::generate(&self.generator, origin_x - pad as f32, origin_z - pad as f32, padded_side, padded_side);
+        let raw = padded.clone();
+        let chunk_seed = ((cx as u32 as u64) << 32 | cz as u32 as u64) ^ (settings.seed as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        erode(&mut padded, &erosion, chunk_seed);
+
+        let mut heights = Vec::with_capacity((side * side) as usize);
+        for z in 0..side {
+            for x in 0..side {
+                let edge_distance = x.min(z).min(side - 1 - x).min(side - 1 - z) as f32;
+                let t = (edge_distance / pad as f32).min(1.0);
+                let weight = t * t * (3.0 - 2.0 * t);
+                let raw_height = raw.height(x + pad, z + pad);
+                heights.push(raw_height + (padded.height(x + pad, z + pad) - raw_height) * weight);
+            }
+        }
+        Heightfield { width: side, depth: side, heights }
+    }
+
+    // Places `heightfield` with its first sample at `origin`, built like a chunk but without
+    // skirts or levels of detail. Loaded chunks under it are freed and no more are generated there.
+    pub fn add_heightfield(&mut self, world: &Ecs, name: &str, heightfield: Heightfield, origin: Vec3) -> Entity {
+        let first = self.chunk_coord(origin.x, origin.z);
+        let last = self.chunk_coord(origin.x + (heightfield.width - 1) as f32, origin.z + (heightfield.depth - 1) as f32);
+        let imported = ImportedTerrain {
+            entity: world.create_entity(name, origin, Vec3::ONE, Vec3::ZERO, None),
+            first,
+            last,
+        };
+        self.chunks.retain(|coord, chunk| {
+            let keep = !imported.covers(*coord);
+            if !keep {
+                world.destroy_entity(chunk.entity);
+            }
+            keep
+        });
+
+        world.add_pbr_shader(imported.entity, self.shader.clone());
+        world.add_mesh(imported.entity, heightfield.create_mesh(), None);
+        world.add_terrain_material(imported.entity, self.material.clone());
+        world.add_terrain(imported.entity, Terrain { heightfield });
+        let entity = imported.entity;
+        self.imported.push(imported);
+        entity
+    }
+
+    // Writes the terrain under world (x, z) to a 16-bit PNG. Generated heights lie in
+    // [0, settings.height], which maps to black and white, so `load_png` with that height reads it back.
+    pub fn export_png(&self, world: &Ecs, x: f32, z: f32, path: &str) -> Result<(), String> {
+        let entity = self.terrain_entity(x, z).ok_or_else(|| format!("No terrain loaded at ({}, {})", x, z))?;
+        let max_height = self.generator.settings.height;
+        entity
+            .entity_view(&world.world)
+            .map::<&Terrain, _>(|terrain| terrain.heightfield.save_png(path, max_height))
+    }
+
+    // Height of the terrain under world (x, z), None where no chunk is loaded yet. Like every
+    // query this follows the full-resolution surface, not the level of detail being drawn.
+    pub fn height_at(&self, world: &Ecs, x: f32, z: f32) -> Option<f32> {
+        let entity = self.terrain_entity(x, z)?;
+        entity.entity_view(&world.world).map::<(&Terrain, &(Transform, Global)), _>(|(terrain, transform)| {
+            terrain.height_at(&transform.0, x, z)
+        })
+    }
+
+    pub fn normal_at(&self, world: &Ecs, x: f32, z: f32) -> Option<Vec3> {
+        let entity = self.terrain_entity(x, z)?;
+        entity.entity_view(&world.world).map::<(&Terrain, &(Transform, Global)), _>(|(terrain, transform)| {
+            terrain.normal_at(&transform.0, x, z)
+        })
+    }
+
+    // Closest hit over all loaded chunks and imported heightfields.
+    pub fn raycast(&self, world: &Ecs, origin: Vec3, dir: Vec3, max_distance: f32) -> Option<TerrainHit> {
+        self.chunks
+            .values()
+            .map(|chunk| chunk.entity)
+            .chain(self.imported.iter().map(|imported| imported.entity))
+            .filter_map(|entity| {
+                entity.entity_view(&world.world).map::<(&Terrain, &(Transform, Global)), _>(|(terrain, transform)| {
+                    terrain.raycast(&transform.0, origin, dir, max_distance)
+                })
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+
+    // Entity whose terrain covers the chunk under world (x, z), imported heightfields first.
+    fn terrain_entity(&self, x: f32, z: f32) -> Option<Entity> {
+        let coord = self.chunk_coord(x, z);
+        self.imported
+            .iter()
+            .find(|imported| imported.covers(coord))
+            .map(|imported| imported.entity)
+            .or_else(|| self.chunks.get(&coord).map(|chunk| chunk.entity))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainHit {
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+// Queries run against the full-resolution triangles exactly as the mesh splits each quad
+// (along the top-right/bottom-left diagonal), in world space through the entity's transform.
+// A streamed chunk drawn at a coarser level of detail differs from them by up to that level's
+// height error, which the LOD selection keeps under `max_pixel_error` on screen.
+impl Terrain {
+    // Height of the surface at world (x, z), None outside the terrain.
+    pub fn height_at(&self, transform: &Mat4, x: f32, z: f32) -> Option<f32> {
+        self.vertical_hit(transform, x, z).map(|hit| hit.position.y)
+    }
+
+    pub fn normal_at(&self, transform: &Mat4, x: f32, z: f32) -> Option<Vec3> {
+        self.vertical_hit(transform, x, z).map(|hit| hit.normal)
+    }
+
+    fn vertical_hit(&self, transform: &Mat4, x: f32, z: f32) -> Option<TerrainHit> {
+        let (min, max) = self.world_bounds(transform);
+        let margin = 1.0;
+        self.raycast(transform, vec3(x, max.y + margin, z), Vec3::NEG_Y, max.y - min.y + 2.0 * margin)
+    }
+
+    fn local_bounds(&self) -> (Vec3, Vec3) {
+        let heightfield = &self.heightfield;
+        let (min_height, max_height) = heightfield
+            .heights
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), &h| (min.min(h), max.max(h)));
+        (
+            vec3(0.0, min_height, 0.0),
+            vec3((heightfield.width - 1) as f32, max_height, (heightfield.depth - 1) as f32),
+        )
+    }
+
+    fn world_bounds(&self, transform: &Mat4) -> (Vec3, Vec3) {
+        let (local_min, local_max) = self.local_bounds();
+        (0..8).fold((Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)), |(min, max), i| {
+            let corner = vec3(
+                if i & 1 == 0 { local_min.x } else { local_max.x },
+                if i & 2 == 0 { local_min.y } else { local_max.y },
+                if i & 4 == 0 { local_min.z } else { local_max.z },
+            );
+            let corner = transform.transform_point3(corner);
+            (min.min(corner), max.max(corner))
+        })
+    }
+
+    // Walks the grid cells under the ray (2D DDA in local space) and tests each cell's two triangles.
+    pub fn raycast(&self, transform: &Mat4, origin: Vec3, dir: Vec3, max_distance: f32) -> Option<TerrainHit> {
+        let dir = dir.normalize();
+        let inverse = transform.inverse();
+        // Transforming the ray keeps its parameter, so local t is the world distance.
+        let local_origin = inverse.transform_point3(origin);
+        let local_dir = inverse.transform_vector3(dir);
+
+        let (min, max) = self.local_bounds();
+        let (mut t_enter, mut t_exit) = (0.0f32, max_distance);
+        for axis in 0..3 {
+            if local_dir[axis].abs() < 1e-8 {
+                if local_origin[axis] < min[axis] || local_origin[axis] > max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (min[axis] - local_origin[axis]) / local_dir[axis];
+            let t1 = (max[axis] - local_origin[axis]) / local_dir[axis];
+            t_enter = t_enter.max(t0.min(t1));
+            t_exit = t_exit.min(t0.max(t1));
+        }
+        if t_enter > t_exit {
+            return None;
+        }
+
+        let last_x = self.heightfield.width as i32 - 2;
+        let last_z = self.heightfield.depth as i32 - 2;
+        let start = local_origin + local_dir * t_enter;
+        let mut cell_x = (start.x.floor() as i32).clamp(0, last_x);
+        let mut cell_z = (start.z.floor() as i32).clamp(0, last_z);
+
+        let step_x = if local_dir.x >= 0.0 { 1 } else { -1 };
+        let step_z = if local_dir.z >= 0.0 { 1 } else { -1 };
+        let next_boundary = |cell: i32, step: i32, origin: f32, dir: f32| {
+            if dir.abs() < 1e-8 {
+                f32::INFINITY
+            } else {
+                ((cell + step.max(0)) as f32 - origin) / dir
+            }
+        };
+        let mut t_max_x = next_boundary(cell_x, step_x, local_origin.x, local_dir.x);
+        let mut t_max_z = next_boundary(cell_z, step_z, local_origin.z, local_dir.z);
+        let t_delta_x = if local_dir.x.abs() < 1e-8 { f32::INFINITY } else { 1.0 / local_dir.x.abs() };
+        let t_delta_z = if local_dir.z.abs() < 1e-8 { f32::INFINITY } else { 1.0 / local_dir.z.abs() };
+
+        loop {
+            if let Some((t, normal)) = self.intersect_cell(cell_x as u32, cell_z as u32, local_origin, local_dir)
+                && t >= t_enter - 1e-4
+                && t <= t_exit + 1e-4
+            {
+                let normal = inverse.transpose().transform_vector3(normal).normalize();
+                return Some(TerrainHit {
+                    position: origin + dir * t,
+                    normal,
+                    distance: t,
+                });
+            }
+
+            if t_max_x < t_max_z {
+                if t_max_x > t_exit {
+                    return None;
+                }
+                cell_x += step_x;
+                t_max_x += t_delta_x;
+            } else {
+                if t_max_z > t_exit {
+                    return None;
+                }
+                cell_z += step_z;
+                t_max_z += t_delta_z;
+            }
+            if cell_x < 0 || cell_x > last_x || cell_z < 0 || cell_z > last_z {
+                return None;
+            }
+        }
+    }
+
+    // Nearest hit of the ray with the two triangles of a grid cell, as (t, local normal).
+    fn intersect_cell(&self, x: u32, z: u32, origin: Vec3, dir: Vec3) -> Option<(f32, Vec3)> {
+        let heightfield = &self.heightfield;
+        let corner = |dx: u32, dz: u32| vec3((x + dx) as f32, heightfield.height(x + dx, z + dz), (z + dz) as f32);
+        let (v00, v10, v01, v11) = (corner(0, 0), corner(1, 0), corner(0, 1), corner(1, 1));
+
+        [[v00, v01, v10], [v10, v01, v11]]
+            .iter()
+            .filter_map(|[a, b, c]| {
+                let t = intersect_triangle(origin, dir, *a, *b, *c)?;
+                let normal = (*b - *a).cross(*c - *a);
+                Some((t, if normal.y < 0.0 { -normal } else { normal }))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+}
+
+// Moller-Trumbore, returns the ray parameter of the hit.
+fn intersect_triangle(origin: Vec3, dir: Vec3, a: Vec3, b: Vec3, c: Vec3) -> Option<f32> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = dir.cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() < 1e-8 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let s = origin - a;
+    let u = s.dot(p) * inv_det;
+    if !(-1e-5..=1.0 + 1e-5).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = dir.dot(q) * inv_det;
+    if v < -1e-5 || u + v > 1.0 + 1e-5 {
+        return None;
+    }
+    Some(edge2.dot(q) * inv_det)
+}
+
+// Grid index of the `i`-th vertex along a chunk border (0 = -z, 1 = +x, 2 = +z, 3 = -x).
+fn border_index(edge: u32, i: u32, side: u32) -> u32 {
+    let last = side - 1;
+    match edge {
+        0 => i,
+        1 => i * side + last,
+        2 => last * side + i,
+        _ => i * side,
+    }
+}
+
+// Triangulates the grid sampling every `step`-th vertex, plus the skirt along all four borders.
+fn lod_indices(size: u32, step: u32, skirt_start: u32) -> Vec<u32> {
+    let side = size + 1;
+    let quads = size / step;
+    let mut indices = Vec::with_capacity((quads * quads * 6 + 4 * quads * 6) as usize);
+
+    for z in (0..size).step_by(step as usize) {
+        for x in (0..size).step_by(step as usize) {
+            let top_left = z * side + x;
+            let top_right = top_left + step;
+            let bottom_left = (z + step) * side + x;
+            let bottom_right = bottom_left + step;
+
+            indices.extend_from_slice(&[top_left, bottom_left, top_right]);
+            indices.extend_from_slice(&[top_right, bottom_left, bottom_right]);
+        }
+    }
+
+    for edge in 0..4 {
+        let skirt = skirt_start + edge * side;
+        for i in (0..size).step_by(step as usize) {
+            let a = border_index(edge, i, side);
+            let b = border_index(edge, i + step, side);
+            indices.extend_from_slice(&[a, skirt + i, b]);
+            indices.extend_from_slice(&[b, skirt + i, skirt + i + step]);
+        }
+    }
+    indices
+}
+
+// Largest vertical distance between the full-resolution heights and the surface
+// the `step` level interpolates over them.
+fn lod_error(heights: &[f32], size: u32, step: u32) -> f32 {
+    if step == 1 {
+        return 0.0;
+    }
+    let side = (size + 1) as usize;
+    let step = step as usize;
+    let height = |x: usize, z: usize| heights[z * side + x];
+
+    let mut error: f32 = 0.0;
+    for z in 0..side {
+        for x in 0..side {
+            let x0 = (x / step * step).min(side - 1 - step);
+            let z0 = (z / step * step).min(side - 1 - step);
+            let fx = (x - x0) as f32 / step as f32;
+            let fz = (z - z0) as f32 / step as f32;
+
+            // Same diagonal split as the index list: top-right to bottom-left.
+            let h00 = height(x0, z0);
+            let h10 = height(x0 + step, z0);
+            let h01 = height(x0, z0 + step);
+            let h11 = height(x0 + step, z0 + step);
+            let approx = if fx + fz <= 1.0 {
+                h00 + (h10 - h00) * fx + (h01 - h00) * fz
+            } else {
+                h11 + (h01 - h11) * (1.0 - fx) + (h10 - h11) * (1.0 - fz)
+            };
+            error = error.max((height(x, z) - approx).abs());
+        }
+    }
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A file under the system temp folder, unique to this process.
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("aurionrs_{}_{}", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn png_round_trip_keeps_16_bit_precision() {
+        let max_height = 40.0;
+        let heights = (0..12).map(|i| i as f32 * 3.3).collect();
+        let heightfield = Heightfield { width: 4, depth: 3, heights };
+        let path = temp_path("round_trip.png");
+        heightfield.save_png(&path, max_height).unwrap();
+        let loaded = Heightfield::load_png(&path, max_height).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((loaded.width, loaded.depth), (4, 3));
+        for (original, loaded) in heightfield.heights.iter().zip(&loaded.heights) {
+            assert!((original - loaded).abs() <= max_height / u16::MAX as f32, "{} came back as {}", original, loaded);
+        }
+    }
+
+    #[test]
+    fn loads_8_bit_grayscale() {
+        let path = temp_path("8_bit.png");
+        image::GrayImage::from_raw(2, 2, vec![0, 51, 204, 255]).unwrap().save(&path).unwrap();
+        let loaded = Heightfield::load_png(&path, 10.0).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((loaded.width, loaded.depth), (2, 2));
+        for (height, expected) in loaded.heights.iter().zip([0.0, 2.0, 8.0, 10.0]) {
+            assert!((height - expected).abs() < 1e-4, "{} != {}", height, expected);
+        }
+    }
+
+    // 2 world units per sample horizontally, 3 vertically, first sample at (10, 5, 20).
+    fn transform() -> Mat4 {
+        Mat4::from_translation(vec3(10.0, 5.0, 20.0)) * Mat4::from_scale(vec3(2.0, 3.0, 2.0))
+    }
+
+    // Flat except the middle sample, so the first quad bends along its diagonal.
+    fn bump() -> Terrain {
+        let heights = vec![0.0, 0.0, 0.0, 0.0, 4.0, 0.0, 0.0, 0.0, 0.0];
+        Terrain { heightfield: Heightfield { width: 3, depth: 3, heights } }
+    }
+
+    // Plane rising one unit per sample along x, 1.5 units per world unit after `transform`.
+    fn slope() -> Terrain {
+        let heights = (0..9).map(|i| (i % 3) as f32).collect();
+        Terrain { heightfield: Heightfield { width: 3, depth: 3, heights } }
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn height_follows_triangles_not_bilinear() {
+        let terrain = bump();
+        // On the diagonal of the first quad both triangles are flat at 0, bilinear would give 1.
+        assert_near(terrain.height_at(&transform(), 11.0, 21.0).unwrap(), 5.0);
+        // Three quarters in, on the triangle touching the bump: 2 in local space.
+        assert_near(terrain.height_at(&transform(), 11.5, 21.5).unwrap(), 5.0 + 2.0 * 3.0);
+        assert_near(terrain.height_at(&transform(), 14.0, 24.0).unwrap(), 5.0);
+    }
+
+    #[test]
+    fn height_outside_grid_is_none() {
+        let terrain = bump();
+        assert!(terrain.height_at(&transform(), 9.0, 21.0).is_none());
+        assert!(terrain.height_at(&transform(), 15.0, 21.0).is_none());
+        assert!(terrain.height_at(&transform(), 11.0, 25.0).is_none());
+        assert!(terrain.normal_at(&transform(), 11.0, 19.0).is_none());
+    }
+
+    #[test]
+    fn normal_of_sloped_quad_in_world_space() {
+        let normal = slope().normal_at(&transform(), 12.5, 21.0).unwrap();
+        assert!(normal.abs_diff_eq(vec3(-1.5, 1.0, 0.0).normalize(), 1e-4), "{}", normal);
+    }
+
+    #[test]
+    fn raycast_hits_slope() {
+        // y = 5 + 1.5 (x - 10) is reached 3 units along x from (10, 12.5).
+        let dir = vec3(1.0, -1.0, 0.0);
+        let hit = slope().raycast(&transform(), vec3(10.0, 12.5, 22.0), dir, 100.0).unwrap();
+        assert!(hit.position.abs_diff_eq(vec3(13.0, 9.5, 22.0), 1e-3), "{}", hit.position);
+        assert_near(hit.distance, 3.0 * 2f32.sqrt());
+        assert!(hit.normal.abs_diff_eq(vec3(-1.5, 1.0, 0.0).normalize(), 1e-4));
+    }
+
+    #[test]
+    fn raycast_misses() {
+        let terrain = slope();
+        // Heading away from the terrain, and pointing at it from too far.
+        assert!(terrain.raycast(&transform(), vec3(10.0, 12.5, 22.0), vec3(-1.0, -1.0, 0.0), 100.0).is_none());
+        assert!(terrain.raycast(&transform(), vec3(12.0, 20.0, 22.0), Vec3::NEG_Y, 5.0).is_none());
+        // Passing over it.
+        assert!(terrain.raycast(&transform(), vec3(5.0, 30.0, 22.0), Vec3::X, 100.0).is_none());
+    }
+
+    #[test]
+    fn vertices_span_grid_with_sample_normals() {
+        let heightfield = Heightfield { width: 3, depth: 2, heights: vec![0.0, 1.0, 2.0, 0.0, 1.0, 2.0] };
+        let vertices = heightfield.vertices();
+        assert_eq!(vertices.len(), 6);
+        assert_eq!(vertices[4].position, vec3(1.0, 1.0, 1.0));
+        assert_eq!(vertices[0].uv, vec2(0.0, 0.0));
+        assert_eq!(vertices[5].uv, vec2(1.0, 1.0));
+        for (i, vertex) in vertices.iter().enumerate() {
+            assert_eq!(vertex.normal, heightfield.normal(i as u32 % 3, i as u32 / 3));
+        }
+        assert!(vertices[1].normal.abs_diff_eq(vec3(-1.0, 1.0, 0.0).normalize(), 1e-6));
+    }
+}
@@ -0,0 +1,3 @@
+vec3 halve(vec3 v) {
+    return v * 0.5;
+}
@@ -0,0 +1,4 @@
+#include "common.glsl"
+vec3 light(vec3 v) {
+    return halve(v);
+}
@@ -0,0 +1,7 @@
+#version 330 core
+#include "common.glsl"
+#include "lighting.glsl"
+out vec4 color;
+void main() {
+    color = vec4(light(halve(vec3(1.0))), 1.0);
+}
@@ -0,0 +1,3 @@
+#version 330 core
+#include "versioned.glsl"
+void main() {}